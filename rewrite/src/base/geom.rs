@@ -1,3 +1,9 @@
+pub type Point = (f64, f64, f64);
+
+fn to_rad(deg: f64) -> f64 {
+    deg * std::f64::consts::PI / 180.0
+}
+
 // Transform
 // from spherical coordinate system (r, theta, phi)
 // to Cartesian coordinate system (x, y, z)
@@ -8,9 +14,6 @@
 //
 // https://keisan.casio.com/exec/system/1359534351
 pub fn to_cartesian(v: (f64, f64, f64)) -> (f64, f64, f64) {
-    fn to_rad(deg: f64) -> f64 {
-        deg * std::f64::consts::PI / 180.0
-    }
     let r = v.0;
     let th = to_rad(v.1);
     let ph = to_rad(v.2);
@@ -19,3 +22,309 @@ pub fn to_cartesian(v: (f64, f64, f64)) -> (f64, f64, f64) {
     let z = r * ph.cos();
     (x, y, z)
 }
+
+// Rotate a point the way OpenSCAD's `rotate([a, b, c])` does:
+// first about x by a, then about y by b, then about z by c (degrees).
+pub fn rotate_point(angles: Point, p: Point) -> Point {
+    let (sa, ca) = to_rad(angles.0).sin_cos();
+    let (sb, cb) = to_rad(angles.1).sin_cos();
+    let (sc, cc) = to_rad(angles.2).sin_cos();
+    let (x, y, z) = p;
+    let (y, z) = (ca * y - sa * z, sa * y + ca * z);
+    let (x, z) = (cb * x + sb * z, -sb * x + cb * z);
+    let (x, y) = (cc * x - sc * y, sc * x + cc * y);
+    (x, y, z)
+}
+
+/// The same angle in degrees, in (-180, 180].
+pub fn normalize_angle(deg: f64) -> f64 {
+    let a = deg.rem_euclid(360.0);
+    if a > 180.0 {
+        a - 360.0
+    } else {
+        a
+    }
+}
+
+/// Angles `(a, b, c)` such that `rotate_point((a, b, c), _)` is the given rotation matrix.
+/// Returns `None` if the matrix is not a rotation.
+pub fn rotation_angles(m: &[[f64; 3]; 3]) -> Option<Point> {
+    let to_deg = |rad: f64| rad * 180.0 / std::f64::consts::PI;
+    // m = Rz(c) Ry(b) Rx(a), so the bottom row is (-sin b, cos b sin a, cos b cos a)
+    let b = (-m[2][0]).clamp(-1.0, 1.0).asin();
+    let (a, c) = if b.cos() > 1e-9 {
+        (m[2][1].atan2(m[2][2]), m[1][0].atan2(m[0][0]))
+    } else {
+        // gimbal lock, only a - c or a + c is determined; take c = 0
+        ((-m[1][2]).atan2(m[1][1]), 0.0)
+    };
+    let angles = (to_deg(a), to_deg(b), to_deg(c));
+
+    let back = Affine3::rotate(angles).lin;
+    let close = (0..3).all(|i| (0..3).all(|j| (back[i][j] - m[i][j]).abs() < 1e-9));
+    close.then_some(angles)
+}
+
+/// A conservative axis-aligned bounding box.
+/// The empty box has `min` at +inf and `max` at -inf.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BBox {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl BBox {
+    pub fn empty() -> BBox {
+        let inf = f64::INFINITY;
+        BBox {
+            min: (inf, inf, inf),
+            max: (-inf, -inf, -inf),
+        }
+    }
+
+    pub fn new(a: Point, b: Point) -> BBox {
+        BBox {
+            min: (a.0.min(b.0), a.1.min(b.1), a.2.min(b.2)),
+            max: (a.0.max(b.0), a.1.max(b.1), a.2.max(b.2)),
+        }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Point>) -> BBox {
+        points
+            .into_iter()
+            .fold(BBox::empty(), |b, p| b.union(&BBox::new(p, p)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.0 > self.max.0 || self.min.1 > self.max.1 || self.min.2 > self.max.2
+    }
+
+    pub fn union(&self, other: &BBox) -> BBox {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        BBox {
+            min: (
+                self.min.0.min(other.min.0),
+                self.min.1.min(other.min.1),
+                self.min.2.min(other.min.2),
+            ),
+            max: (
+                self.max.0.max(other.max.0),
+                self.max.1.max(other.max.1),
+                self.max.2.max(other.max.2),
+            ),
+        }
+    }
+
+    pub fn inter(&self, other: &BBox) -> BBox {
+        let b = BBox {
+            min: (
+                self.min.0.max(other.min.0),
+                self.min.1.max(other.min.1),
+                self.min.2.max(other.min.2),
+            ),
+            max: (
+                self.max.0.min(other.max.0),
+                self.max.1.min(other.max.1),
+                self.max.2.min(other.max.2),
+            ),
+        };
+        if b.is_empty() {
+            BBox::empty()
+        } else {
+            b
+        }
+    }
+
+    pub fn corners(&self) -> [Point; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            (a.0, a.1, a.2),
+            (b.0, a.1, a.2),
+            (a.0, b.1, a.2),
+            (b.0, b.1, a.2),
+            (a.0, a.1, b.2),
+            (b.0, a.1, b.2),
+            (a.0, b.1, b.2),
+            (b.0, b.1, b.2),
+        ]
+    }
+
+    // the image of the box under a point map, valid for linear maps
+    fn map(&self, f: impl Fn(Point) -> Point) -> BBox {
+        if self.is_empty() {
+            return *self;
+        }
+        BBox::from_points(self.corners().iter().map(|&p| f(p)))
+    }
+
+    pub fn translate(&self, v: Point) -> BBox {
+        self.map(|p| (p.0 + v.0, p.1 + v.1, p.2 + v.2))
+    }
+
+    pub fn scale(&self, s: Point) -> BBox {
+        self.map(|p| (p.0 * s.0, p.1 * s.1, p.2 * s.2))
+    }
+
+    pub fn rotate(&self, angles: Point) -> BBox {
+        self.map(|p| rotate_point(angles, p))
+    }
+
+    pub fn mirror(&self, normal: Point) -> BBox {
+        match Affine3::mirror(normal) {
+            Some(m) => self.map(|p| m.apply(p)),
+            None => *self,
+        }
+    }
+
+    /// Whether the two boxes are separated by a gap wider than `eps` along some axis.
+    pub fn is_disjoint(&self, other: &BBox, eps: f64) -> bool {
+        if self.is_empty() || other.is_empty() {
+            return true;
+        }
+        let apart =
+            |amin: f64, amax: f64, bmin: f64, bmax: f64| amax + eps < bmin || bmax + eps < amin;
+        apart(self.min.0, self.max.0, other.min.0, other.max.0)
+            || apart(self.min.1, self.max.1, other.min.1, other.max.1)
+            || apart(self.min.2, self.max.2, other.min.2, other.max.2)
+    }
+}
+
+/// An affine map `p -> lin * p + off`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Affine3 {
+    pub lin: [[f64; 3]; 3],
+    pub off: Point,
+}
+
+impl Affine3 {
+    pub fn identity() -> Affine3 {
+        Affine3::linear([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+    }
+
+    pub fn linear(lin: [[f64; 3]; 3]) -> Affine3 {
+        Affine3 {
+            lin,
+            off: (0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn translate(v: Point) -> Affine3 {
+        Affine3 {
+            off: v,
+            ..Affine3::identity()
+        }
+    }
+
+    pub fn scale(s: Point) -> Affine3 {
+        Affine3::linear([[s.0, 0.0, 0.0], [0.0, s.1, 0.0], [0.0, 0.0, s.2]])
+    }
+
+    pub fn rotate(angles: Point) -> Affine3 {
+        let x = rotate_point(angles, (1.0, 0.0, 0.0));
+        let y = rotate_point(angles, (0.0, 1.0, 0.0));
+        let z = rotate_point(angles, (0.0, 0.0, 1.0));
+        Affine3::linear([[x.0, y.0, z.0], [x.1, y.1, z.1], [x.2, y.2, z.2]])
+    }
+
+    /// The reflection in the plane through the origin with this normal, like OpenSCAD's
+    /// `mirror`. `None` for a zero normal, which OpenSCAD treats as the identity.
+    pub fn mirror(n: Point) -> Option<Affine3> {
+        let len2 = n.0 * n.0 + n.1 * n.1 + n.2 * n.2;
+        if len2 == 0.0 {
+            return None;
+        }
+        let n = [n.0, n.1, n.2];
+        let mut lin = [[0.0; 3]; 3];
+        for (i, row) in lin.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                let id = if i == j { 1.0 } else { 0.0 };
+                *x = id - 2.0 * n[i] * n[j] / len2;
+            }
+        }
+        Some(Affine3::linear(lin))
+    }
+
+    pub fn apply(&self, p: Point) -> Point {
+        let m = &self.lin;
+        (
+            m[0][0] * p.0 + m[0][1] * p.1 + m[0][2] * p.2 + self.off.0,
+            m[1][0] * p.0 + m[1][1] * p.1 + m[1][2] * p.2 + self.off.1,
+            m[2][0] * p.0 + m[2][1] * p.1 + m[2][2] * p.2 + self.off.2,
+        )
+    }
+
+    /// `self.then(other)` applies `self` first and `other` second.
+    pub fn then(&self, other: &Affine3) -> Affine3 {
+        let (a, b) = (&other.lin, &self.lin);
+        let mut lin = [[0.0; 3]; 3];
+        for (i, row) in lin.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+            }
+        }
+        Affine3 {
+            lin,
+            off: other.apply(self.off),
+        }
+    }
+
+    pub fn det(&self) -> f64 {
+        let m = &self.lin;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn inverse(&self) -> Option<Affine3> {
+        let det = self.det();
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+        let m = &self.lin;
+        let cof = |i: usize, j: usize| {
+            let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+            let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let mut lin = [[0.0; 3]; 3];
+        for (i, row) in lin.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = cof(j, i) / det;
+            }
+        }
+        let inv = Affine3::linear(lin);
+        let o = inv.apply(self.off);
+        Some(Affine3 {
+            off: (-o.0, -o.1, -o.2),
+            ..inv
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disjoint_boxes() {
+        let unit = BBox::new((0.0, 0.0, 0.0), (1.0, 1.0, 1.0));
+        let at = |x: f64, z: f64| unit.translate((x, 0.0, z));
+        assert!(unit.is_disjoint(&at(2.0, 0.0), 0.0));
+        assert!(at(0.0, -2.0).is_disjoint(&unit, 0.0));
+        // apart along one axis is enough
+        assert!(unit.is_disjoint(&at(0.5, 1.5), 0.0));
+
+        assert!(!unit.is_disjoint(&at(0.5, 0.5), 0.0));
+        // touching faces and gaps within eps are not apart
+        assert!(!unit.is_disjoint(&at(1.0, 0.0), 0.0));
+        assert!(!unit.is_disjoint(&at(1.001, 0.0), 0.01));
+        assert!(unit.is_disjoint(&at(1.1, 0.0), 0.01));
+
+        assert!(BBox::empty().is_disjoint(&unit, 0.0));
+    }
+}
@@ -30,6 +30,10 @@ impl Permutation {
         self.order.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
     pub fn is_ordered(&self) -> bool {
         self.order.iter().enumerate().all(|(a, b)| a == *b)
     }
@@ -2,6 +2,10 @@
 // floating point number
 pub mod num;
 
+// big rational number, the Num of the exact mode
+#[cfg(feature = "exact")]
+pub mod rational;
+
 // geometry
 pub mod geom;
 
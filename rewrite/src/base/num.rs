@@ -2,24 +2,46 @@
 ///
 ///
 use std::fmt;
+use std::ops::{Add, Mul, Sub};
 use std::str::FromStr;
 
+#[cfg(feature = "exact")]
+use num_rational::BigRational;
+#[cfg(feature = "exact")]
+use num_traits::{ToPrimitive, Zero};
+
+#[cfg(feature = "exact")]
+use crate::base::rational;
 use crate::hyperparameters::{ABS_EPSILON, REL_EPSILON};
 
-/// A basic data structure abstraction for floaing point number
-#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Default, Clone, Copy)]
+/// A basic data structure abstraction for floaing point number. It is not `Copy`, so that
+/// the code builds the same in the exact mode.
+#[cfg(not(feature = "exact"))]
+#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Default, Clone)]
 pub struct Num(ordered_float::NotNan<f64>);
 
+/// In the exact mode, a rational number. Only the lines and polynomials are fitted in
+/// rationals, the other formulas and `to_f64` go through floats and are as exact as they are.
+#[cfg(feature = "exact")]
+#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Default, Clone)]
+pub struct Num(BigRational);
+
 pub fn num(n: impl Into<Num>) -> Num {
     n.into()
 }
 
 impl Num {
-    pub fn to_f64(self) -> f64 {
+    #[cfg(not(feature = "exact"))]
+    pub fn to_f64(&self) -> f64 {
         self.0.into_inner()
     }
 
-    pub fn is_close(self, other: impl Clone + Into<Num>) -> bool {
+    #[cfg(feature = "exact")]
+    pub fn to_f64(&self) -> f64 {
+        self.0.to_f64().unwrap()
+    }
+
+    pub fn is_close(&self, other: impl Into<Num>) -> bool {
         let a = self.to_f64();
         let b = other.into().to_f64();
 
@@ -32,15 +54,39 @@ impl Num {
             })
             .is_some()
     }
+
+    /// The number of digits it takes to print, without leading zeros, or the trailing zeros
+    /// of a whole number, so 25000 is as short as 25.
+    pub fn significant_digits(&self) -> usize {
+        let s = self.to_string();
+        let digits: String = s
+            .chars()
+            .filter(|c| c.is_ascii_digit())
+            .skip_while(|&c| c == '0')
+            .collect();
+        if s.contains('.') {
+            digits.len()
+        } else {
+            digits.trim_end_matches('0').len()
+        }
+    }
 }
 
 // conversions
+#[cfg(not(feature = "exact"))]
 impl From<f64> for Num {
     fn from(f: f64) -> Num {
         Num(f.into())
     }
 }
 
+#[cfg(feature = "exact")]
+impl From<f64> for Num {
+    fn from(f: f64) -> Num {
+        Num(rational::from_f64(f).expect("Num must be finite"))
+    }
+}
+
 impl From<usize> for Num {
     fn from(u: usize) -> Num {
         let f = u as f64;
@@ -55,24 +101,115 @@ impl From<i32> for Num {
     }
 }
 
+impl From<&Num> for Num {
+    fn from(n: &Num) -> Num {
+        n.clone()
+    }
+}
+
+// arithmetic, exact in the exact mode
+macro_rules! num_op {
+    ($trait:ident, $fn:ident, $op:tt) => {
+        impl $trait for Num {
+            type Output = Num;
+
+            #[cfg(not(feature = "exact"))]
+            fn $fn(self, other: Num) -> Num {
+                num(self.to_f64() $op other.to_f64())
+            }
+
+            #[cfg(feature = "exact")]
+            fn $fn(self, other: Num) -> Num {
+                Num(self.0 $op other.0)
+            }
+        }
+
+        impl<'a> $trait<&'a Num> for &'a Num {
+            type Output = Num;
+
+            #[cfg(not(feature = "exact"))]
+            fn $fn(self, other: &Num) -> Num {
+                num(self.to_f64() $op other.to_f64())
+            }
+
+            #[cfg(feature = "exact")]
+            fn $fn(self, other: &Num) -> Num {
+                Num(&self.0 $op &other.0)
+            }
+        }
+    };
+}
+
+num_op!(Add, add, +);
+num_op!(Sub, sub, -);
+num_op!(Mul, mul, *);
+
+// division, None for a zero divisor instead of a panic or an infinity
+macro_rules! checked_op {
+    ($fn:ident, $op:tt) => {
+        impl Num {
+            #[cfg(not(feature = "exact"))]
+            pub fn $fn(&self, other: &Num) -> Option<Num> {
+                let f = self.to_f64() $op other.to_f64();
+                (other.to_f64() != 0.0 && f.is_finite()).then(|| num(f))
+            }
+
+            #[cfg(feature = "exact")]
+            pub fn $fn(&self, other: &Num) -> Option<Num> {
+                (!other.0.is_zero()).then(|| Num(&self.0 $op &other.0))
+            }
+        }
+    };
+}
+
+checked_op!(checked_div, /);
+checked_op!(checked_rem, %);
+
 // core traits
 impl FromStr for Num {
     type Err = ordered_float::ParseNotNanError<std::num::ParseFloatError>;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        #[cfg(feature = "exact")]
+        if let Some(r) = rational::parse(s) {
+            return Ok(Num(r));
+        }
         let f: ordered_float::NotNan<f64> = s.parse()?;
         Ok(f.into_inner().into())
     }
 }
 
 impl fmt::Display for Num {
+    #[cfg(not(feature = "exact"))]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let float = self.to_f64();
         write!(f, "{}", float)
     }
+
+    // exactly, so that an exported program reads back as the same numbers
+    #[cfg(feature = "exact")]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        rational::display(&self.0, f)
+    }
 }
 
 impl fmt::Debug for Num {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Num({})", self.to_f64())
+        write!(f, "Num({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(num(3).checked_div(&num(2)), Some(num(1.5)));
+        assert_eq!(num(3).checked_div(&num(0)), None);
+        assert_eq!(num(7).checked_rem(&num(0)), None);
+        assert_eq!(
+            "0.5".parse::<Num>().unwrap(),
+            num(1).checked_div(&num(2)).unwrap()
+        );
     }
 }
@@ -0,0 +1,131 @@
+/// Rational
+/// Parsing and printing of big rational numbers, the `Num` of the exact mode. A number
+/// prints as a decimal when it has one, like `0.125`, and as a fraction like `1/3` else,
+/// so that it reads back as the same number.
+use std::fmt;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{pow, One, Signed, Zero};
+
+fn decimal(s: &str) -> Option<BigRational> {
+    let (mantissa, exp) = match s.split_once(['e', 'E']) {
+        Some((m, e)) => (m, e.parse::<i32>().ok()?),
+        None => (s, 0),
+    };
+    let (neg, mantissa) = match mantissa.strip_prefix('-') {
+        Some(m) => (true, m),
+        None => (false, mantissa.strip_prefix('+').unwrap_or(mantissa)),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", int, frac);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut numer: BigInt = digits.parse().ok()?;
+    if neg {
+        numer = -numer;
+    }
+    let exp = exp - frac.len() as i32;
+    let scale = pow(BigInt::from(10), exp.unsigned_abs() as usize);
+    if exp >= 0 {
+        Some(BigRational::from_integer(numer * scale))
+    } else {
+        Some(BigRational::new(numer, scale))
+    }
+}
+
+/// The exact value of a decimal like `-12.5` or `1e-3`, or of a fraction like `-1/3`.
+pub fn parse(s: &str) -> Option<BigRational> {
+    match s.split_once('/') {
+        Some((p, q)) => {
+            let (p, q) = (decimal(p)?, decimal(q)?);
+            (!q.is_zero()).then(|| p / q)
+        }
+        None => decimal(s),
+    }
+}
+
+/// The decimal that prints as the float, not its binary expansion, so 0.1 is 1/10.
+pub fn from_f64(f: f64) -> Option<BigRational> {
+    if f.is_finite() {
+        decimal(&f.to_string())
+    } else {
+        None
+    }
+}
+
+// the number of decimals of p/q, if q only has the factors 2 and 5
+fn decimals(q: &BigInt) -> Option<usize> {
+    let mut q = q.clone();
+    let mut count = |factor: BigInt| {
+        let mut n = 0;
+        while (&q % &factor).is_zero() {
+            q /= &factor;
+            n += 1;
+        }
+        n
+    };
+    let places = usize::max(count(2.into()), count(5.into()));
+    q.is_one().then_some(places)
+}
+
+pub fn display(r: &BigRational, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let (p, q) = (r.numer(), r.denom());
+    let places = match decimals(q) {
+        Some(places) => places,
+        None => return write!(f, "{}/{}", p, q),
+    };
+    let digits = (p.abs() * pow(BigInt::from(10), places) / q).to_string();
+    let sign = if p.is_negative() { "-" } else { "" };
+    if places == 0 {
+        return write!(f, "{}{}", sign, digits);
+    }
+    let digits = format!("{:0>width$}", digits, width = places + 1);
+    let (int, frac) = digits.split_at(digits.len() - places);
+    write!(f, "{}{}.{}", sign, int, frac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Show(BigRational);
+    impl fmt::Display for Show {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            display(&self.0, f)
+        }
+    }
+
+    fn ratio(p: i64, q: i64) -> BigRational {
+        BigRational::new(p.into(), q.into())
+    }
+
+    #[test]
+    fn exact_decimals() {
+        assert_eq!(parse("-12.5"), Some(ratio(-25, 2)));
+        assert_eq!(parse("1e-3"), Some(ratio(1, 1000)));
+        assert_eq!(parse("2.5E2"), Some(ratio(250, 1)));
+        assert_eq!(parse("-1/3"), Some(ratio(-1, 3)));
+        assert_eq!(parse("1/0"), None);
+        assert_eq!(parse("inf"), None);
+        assert_eq!(from_f64(0.1), Some(ratio(1, 10)));
+    }
+
+    #[test]
+    fn prints_back() {
+        for (r, s) in [
+            (ratio(-25, 2), "-12.5"),
+            (ratio(1, 1000), "0.001"),
+            (ratio(-3, 40), "-0.075"),
+            (ratio(250, 1), "250"),
+            (ratio(0, 1), "0"),
+            (ratio(100, 3), "100/3"),
+            (ratio(-1, 3), "-1/3"),
+        ] {
+            assert_eq!(Show(r.clone()).to_string(), s);
+            assert_eq!(parse(s), Some(r));
+        }
+    }
+}
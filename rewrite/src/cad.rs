@@ -6,6 +6,7 @@ use log::debug;
 use egg::*;
 
 use crate::{
+    base::geom::{to_cartesian, BBox, Point},
     base::list_op::{Partitioning, Permutation},
     base::num::{num, Num},
     cost::{Cost, CostFn},
@@ -66,6 +67,8 @@ define_language! {
 
         // TODO: mapI could be a smallvec
         "MapI" = MapI(Vec<Id>),
+        // a MapI bound counting from start by step, instead of from 0 by 1
+        "Range" = Range([Id; 3]),
         ListVar(ListVar),
         "Repeat" = Repeat([Id; 2]),
 
@@ -73,12 +76,16 @@ define_language! {
         "TransPolar" = TransPolar,
         "Scale" = Scale,
         "Rotate" = Rotate,
+        // reflection in the plane through the origin with the given normal
+        "Mirror" = Mirror,
 
         "Union" = Union,
         "Diff" = Diff,
         "Inter" = Inter,
 
         "Map2" = Map2([Id; 3]),
+        // element k of a list with an affine applied to it k times
+        "Iterate" = Iterate([Id; 4]),
         "Fold" = Fold([Id; 2]),
         "Affine" = Affine([Id; 3]),
         "Binop" = Binop([Id; 3]),
@@ -94,6 +101,8 @@ define_language! {
         "Part" = Part([Id; 2]),
         "Unpart" = Unpart([Id; 2]),
         "Unpolar" = Unpolar([Id; 3]),
+        // the list without the elements at a list of indices
+        "Remove" = Remove([Id; 2]),
 
         Permutation(Permutation),
         Partitioning(Partitioning),
@@ -102,6 +111,13 @@ define_language! {
         "-" = Sub([Id; 2]),
         "*" = Mul([Id; 2]),
         "/" = Div([Id; 2]),
+        "pow" = Pow([Id; 2]),
+        "%" = Mod([Id; 2]),
+        // in degrees, like OpenSCAD
+        "sin" = Sin([Id; 1]),
+        "cos" = Cos([Id; 1]),
+        "sqrt" = Sqrt([Id; 1]),
+        "PI" = Pi,
         BlackBox(BlackBox, Vec<Id>),
     }
 }
@@ -113,6 +129,7 @@ pub struct Meta {
     pub list: Option<Vec<Id>>,
     pub cost: Cost,
     pub best: Cad,
+    pub bbox: Option<BBox>,
 }
 
 fn eval(egraph: &EGraph, enode: &Cad) -> Option<Cad> {
@@ -121,38 +138,152 @@ fn eval(egraph: &EGraph, enode: &Cad) -> Option<Cad> {
         Add(args) => {
             assert_eq!(args.len(), 2);
             match (&egraph[args[0]].data.best, &egraph[args[1]].data.best) {
-                (Num(f1), Num(f2)) => Some(Num(num(f1.to_f64() + f2.to_f64()))),
+                (Num(f1), Num(f2)) => Some(Num(f1 + f2)),
                 _ => None,
             }
         }
         Sub(args) => {
             assert_eq!(args.len(), 2);
             match (&egraph[args[0]].data.best, &egraph[args[1]].data.best) {
-                (Num(f1), Num(f2)) => Some(Num(num(f1.to_f64() - f2.to_f64()))),
+                (Num(f1), Num(f2)) => Some(Num(f1 - f2)),
                 _ => None,
             }
         }
         Mul(args) => {
             assert_eq!(args.len(), 2);
             match (&egraph[args[0]].data.best, &egraph[args[1]].data.best) {
-                (Num(f1), Num(f2)) => Some(Num(num(f1.to_f64() * f2.to_f64()))),
+                (Num(f1), Num(f2)) => Some(Num(f1 * f2)),
                 _ => None,
             }
         }
         Div(args) => {
             assert_eq!(args.len(), 2);
             match (&egraph[args[0]].data.best, &egraph[args[1]].data.best) {
-                (Num(f1), Num(f2)) => {
-                    let f = f1.to_f64() / f2.to_f64();
-                    if f.is_finite() && !f2.is_close(0) {
-                        Some(Num(num(f)))
-                    } else {
-                        None
-                    }
+                (Num(f1), Num(f2)) if !f2.is_close(0) => f1.checked_div(f2).map(Num),
+                _ => None,
+            }
+        }
+        Pow(args) => match (&egraph[args[0]].data.best, &egraph[args[1]].data.best) {
+            (Num(f1), Num(f2)) => {
+                let f = f1.to_f64().powf(f2.to_f64());
+                f.is_finite().then(|| Num(num(f)))
+            }
+            _ => None,
+        },
+        Mod(args) => match (&egraph[args[0]].data.best, &egraph[args[1]].data.best) {
+            (Num(f1), Num(f2)) if !f2.is_close(0) => f1.checked_rem(f2).map(Num),
+            _ => None,
+        },
+        Sin(args) => match &egraph[args[0]].data.best {
+            Num(f) => Some(Num(num(f.to_f64().to_radians().sin()))),
+            _ => None,
+        },
+        Cos(args) => match &egraph[args[0]].data.best {
+            Num(f) => Some(Num(num(f.to_f64().to_radians().cos()))),
+            _ => None,
+        },
+        Sqrt(args) => match &egraph[args[0]].data.best {
+            Num(f) => {
+                let f = f.to_f64().sqrt();
+                f.is_finite().then(|| Num(num(f)))
+            }
+            _ => None,
+        },
+        Pi => Some(Num(num(std::f64::consts::PI))),
+        _ => None,
+    }
+}
+
+fn get_num(egraph: &EGraph, id: Id) -> Option<f64> {
+    match &egraph[id].data.best {
+        Cad::Num(n) => Some(n.to_f64()),
+        _ => None,
+    }
+}
+
+fn get_point(egraph: &EGraph, id: Id) -> Option<Point> {
+    match egraph[id].data.best {
+        Cad::Vec3(args) => Some((
+            get_num(egraph, args[0])?,
+            get_num(egraph, args[1])?,
+            get_num(egraph, args[2])?,
+        )),
+        _ => None,
+    }
+}
+
+// Combine the boxes of the operands of a boolean operator.
+// `None` stands for an unknown box, which is unbounded.
+fn bool_bbox(op: &Cad, boxes: &[Option<BBox>]) -> Option<BBox> {
+    match op {
+        Cad::Union => boxes
+            .iter()
+            .try_fold(BBox::empty(), |acc, b| b.map(|b| acc.union(&b))),
+        Cad::Inter => boxes.iter().flatten().fold(None, |acc, b| {
+            Some(acc.map_or(*b, |acc: BBox| acc.inter(b)))
+        }),
+        Cad::Diff => boxes.first().copied().flatten(),
+        _ => None,
+    }
+}
+
+// A conservative axis-aligned bounding box of the solid denoted by `enode`.
+fn bbox(egraph: &EGraph, enode: &Cad) -> Option<BBox> {
+    let get_bbox = |id: Id| egraph[id].data.bbox;
+    match enode {
+        Cad::Empty => Some(BBox::empty()),
+        Cad::Cube(args) => {
+            let size = get_point(egraph, args[0])?;
+            match egraph[args[1]].data.best {
+                Cad::Bool(true) => {
+                    let half = (size.0 / 2.0, size.1 / 2.0, size.2 / 2.0);
+                    Some(BBox::new((-half.0, -half.1, -half.2), half))
                 }
+                Cad::Bool(false) => Some(BBox::new((0.0, 0.0, 0.0), size)),
+                _ => None,
+            }
+        }
+        Cad::Sphere(args) => {
+            let r = get_num(egraph, args[0])?.abs();
+            Some(BBox::new((-r, -r, -r), (r, r, r)))
+        }
+        Cad::Cylinder(args) => {
+            let (h, r1, r2) = get_point(egraph, args[0])?;
+            let r = r1.abs().max(r2.abs());
+            let (z0, z1) = match egraph[args[2]].data.best {
+                Cad::Bool(true) => (-h / 2.0, h / 2.0),
+                Cad::Bool(false) => (0.0, h),
+                _ => return None,
+            };
+            Some(BBox::new((-r, -r, z0), (r, r, z1)))
+        }
+        Cad::Hull(args) => {
+            let ids = egraph[args[0]].data.list.as_ref()?;
+            let boxes: Vec<_> = ids.iter().map(|&id| get_bbox(id)).collect();
+            bool_bbox(&Cad::Union, &boxes)
+        }
+        Cad::Affine(args) => {
+            let inner = get_bbox(args[2])?;
+            let param = get_point(egraph, args[1])?;
+            match egraph[args[0]].data.best {
+                Cad::Trans => Some(inner.translate(param)),
+                Cad::TransPolar => Some(inner.translate(to_cartesian(param))),
+                Cad::Scale => Some(inner.scale(param)),
+                Cad::Rotate => Some(inner.rotate(param)),
+                Cad::Mirror => Some(inner.mirror(param)),
                 _ => None,
             }
         }
+        Cad::Binop(args) => {
+            let op = &egraph[args[0]].data.best;
+            bool_bbox(op, &[get_bbox(args[1]), get_bbox(args[2])])
+        }
+        Cad::Fold(args) => {
+            let op = &egraph[args[0]].data.best;
+            let ids = egraph[args[1]].data.list.as_ref()?;
+            let boxes: Vec<_> = ids.iter().map(|&id| get_bbox(id)).collect();
+            bool_bbox(op, &boxes)
+        }
         _ => None,
     }
 }
@@ -175,6 +306,15 @@ impl Analysis<Cad> for MetaAnalysis {
             a.best = b.best;
         }
 
+        // both boxes are conservative, so their intersection is too
+        let bbox = match (a.bbox, b.bbox) {
+            (Some(x), Some(y)) => Some(x.inter(&y)),
+            (x, y) => x.or(y),
+        };
+        did_merge.0 |= bbox != a.bbox;
+        did_merge.1 |= bbox != b.bbox;
+        a.bbox = bbox;
+
         did_merge
     }
     fn make(egraph: &EGraph, enode: &Cad) -> Self::Data {
@@ -197,7 +337,14 @@ impl Analysis<Cad> for MetaAnalysis {
             _ => None,
         };
 
-        Self::Data { list, best, cost }
+        let bbox = bbox(egraph, enode);
+
+        Self::Data {
+            list,
+            best,
+            cost,
+            bbox,
+        }
     }
 
     fn modify(egraph: &mut EGraph, id: Id) {
@@ -248,3 +395,57 @@ pub fn println_cad(egraph: &EGraph, id: Id) {
     println_cad_impl(egraph, id);
     println!();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox_of(expr: &str) -> Option<BBox> {
+        let mut egraph = EGraph::new(MetaAnalysis);
+        let id = egraph.add_expr(&expr.parse().unwrap());
+        egraph.rebuild();
+        egraph[id].data.bbox
+    }
+
+    #[test]
+    fn bounding_boxes() {
+        let b = |a: Point, c: Point| Some(BBox::new(a, c));
+        assert_eq!(
+            bbox_of("(Cube (Vec3 2 4 6) false)"),
+            b((0.0, 0.0, 0.0), (2.0, 4.0, 6.0))
+        );
+        assert_eq!(
+            bbox_of("(Cube (Vec3 2 4 6) true)"),
+            b((-1.0, -2.0, -3.0), (1.0, 2.0, 3.0))
+        );
+        assert_eq!(
+            bbox_of("(Cylinder (Vec3 4 1 3) (Vec3 0 0 0) true)"),
+            b((-3.0, -3.0, -2.0), (3.0, 3.0, 2.0))
+        );
+        assert_eq!(
+            bbox_of("(Affine Trans (Vec3 1 2 3) (Sphere 1 (Vec3 0 0 0)))"),
+            b((0.0, 1.0, 2.0), (2.0, 3.0, 4.0))
+        );
+        assert_eq!(
+            bbox_of("(Affine Scale (Vec3 -2 1 1) (Cube (Vec3 1 1 1) false))"),
+            b((-2.0, 0.0, 0.0), (0.0, 1.0, 1.0))
+        );
+
+        let a = "(Cube (Vec3 2 2 2) false)";
+        let c = "(Affine Trans (Vec3 1 1 1) (Cube (Vec3 2 2 2) false))";
+        assert_eq!(
+            bbox_of(&format!("(Fold Union (List {} {}))", a, c)),
+            b((0.0, 0.0, 0.0), (3.0, 3.0, 3.0))
+        );
+        assert_eq!(
+            bbox_of(&format!("(Fold Inter (List {} {}))", a, c)),
+            b((1.0, 1.0, 1.0), (2.0, 2.0, 2.0))
+        );
+        // a difference is within its head
+        assert_eq!(
+            bbox_of(&format!("(Binop Diff {} {})", a, c)),
+            b((0.0, 0.0, 0.0), (2.0, 2.0, 2.0))
+        );
+        assert_eq!(bbox_of("(Vec3 1 2 3)"), None);
+    }
+}
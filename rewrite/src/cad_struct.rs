@@ -2,7 +2,7 @@ use crate::cad::Cad;
 use egg::{Id, RecExpr};
 
 pub fn get_num(expr: &RecExpr<Cad>, p: Id) -> f64 {
-    match expr[p] {
+    match &expr[p] {
         Cad::Num(num) => num.to_f64(),
         _ => panic!("Not a num"), // is panic the right thing?
     }
@@ -0,0 +1,787 @@
+/// Check
+/// Test-time soundness checks for rewrites. A rule is checked by instantiating its left-hand side
+/// with random ground terms, applying it once, and comparing both sides of every union it
+/// performed. Values are compared after flattening with `eval`, solids by point membership.
+/// The same comparison can re-validate a sample of the unions of a whole run.
+use std::collections::HashMap;
+use std::mem::discriminant;
+use std::panic::{self, AssertUnwindSafe};
+
+use egg::{ENodeOrVar, Id, Language, PatternAst, RecExpr, Var};
+use indexmap::IndexMap;
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_pcg::Pcg64;
+use serde::Serialize;
+
+use crate::{
+    base::list_op::{Partitioning, Permutation},
+    cad::{Cad, EGraph, ListVar, MetaAnalysis, Rewrite},
+    eval::eval,
+    hyperparameters::{CHECK_INSTANCES, CHECK_SAMPLES, CHECK_SEED, CHECK_TOLERANCE},
+    solid::Solid,
+};
+
+// SZ_TRACE_SAMPLE: how many unions of a run to re-validate, 0 turns tracing off
+sz_param!(TRACE_SAMPLE: usize = 0);
+
+/// The number of unions to re-validate after each run, from `SZ_TRACE_SAMPLE`.
+/// Tracing needs explanations to be enabled on the runner.
+pub fn trace_sample() -> usize {
+    *TRACE_SAMPLE
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Same,
+    Different(String),
+    /// One of the sides could not be evaluated.
+    Unknown,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RuleReport {
+    pub name: String,
+    /// instances the rule matched
+    pub fired: usize,
+    /// unions compared
+    pub checked: usize,
+    /// unions where a side could not be evaluated
+    pub unchecked: usize,
+    pub mismatches: Vec<String>,
+}
+
+/// The unions made by rules during a run, and the outcome of re-validating a sample of them.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UnionTrace {
+    /// number of unions per rule
+    pub unions: IndexMap<String, usize>,
+    pub checked: usize,
+    pub unchecked: usize,
+    pub mismatches: Vec<String>,
+}
+
+fn describe(lhs: &RecExpr<Cad>, rhs: &RecExpr<Cad>, why: &str) -> String {
+    format!("{}\n  <=>\n{}\n  ({})", lhs.pretty(80), rhs.pretty(80), why)
+}
+
+// Run `f`, turning a panic into `None` without printing it.
+fn quietly<T>(f: impl FnOnce() -> T) -> Option<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).ok()
+}
+
+fn normalize(expr: &RecExpr<Cad>, ctx: &HashMap<&'static str, f64>) -> (RecExpr<Cad>, Id) {
+    let mut out = RecExpr::from(vec![]);
+    let root = (expr.as_ref().len() - 1).into();
+    let p = eval(Some(ctx), expr, root, &mut out);
+    (out, p)
+}
+
+fn is_solid(cad: &Cad) -> bool {
+    use Cad::*;
+    matches!(
+        cad,
+        Cube(_) | Sphere(_) | Cylinder(_) | Empty | Hull(_) | Affine(_) | Fold(_) | BlackBox(..)
+    )
+}
+
+fn same_solid(a: &Solid, b: &Solid, rng: &mut Pcg64) -> Result<(), String> {
+    let bbox = a.bbox().union(&b.bbox());
+    if bbox.is_empty() {
+        return Ok(());
+    }
+    let mut along = |lo: f64, hi: f64| lo + (hi - lo) * rng.gen::<f64>();
+    let differ = (0..CHECK_SAMPLES)
+        .filter(|_| {
+            let p = (
+                along(bbox.min.0, bbox.max.0),
+                along(bbox.min.1, bbox.max.1),
+                along(bbox.min.2, bbox.max.2),
+            );
+            a.contains(p) != b.contains(p)
+        })
+        .count();
+    let ratio = differ as f64 / CHECK_SAMPLES as f64;
+    if ratio > CHECK_TOLERANCE {
+        Err(format!("solids differ at {:.1}% of points", 100.0 * ratio))
+    } else {
+        Ok(())
+    }
+}
+
+fn same_value(
+    ea: &RecExpr<Cad>,
+    pa: Id,
+    eb: &RecExpr<Cad>,
+    pb: Id,
+    rng: &mut Pcg64,
+) -> Result<(), String> {
+    let (a, b) = (&ea[pa], &eb[pb]);
+    if is_solid(a) && is_solid(b) {
+        let solids = (
+            Solid::from_normalform(ea, pa),
+            Solid::from_normalform(eb, pb),
+        );
+        if let (Some(sa), Some(sb)) = solids {
+            return same_solid(&sa, &sb, rng);
+        }
+    }
+    match (a, b) {
+        (Cad::Num(x), Cad::Num(y)) if x.is_close(y) => Ok(()),
+        _ if discriminant(a) != discriminant(b) || a.children().len() != b.children().len() => {
+            Err(format!("{} vs {}", a, b))
+        }
+        _ if a.is_leaf() => {
+            if a == b {
+                Ok(())
+            } else {
+                Err(format!("{} vs {}", a, b))
+            }
+        }
+        _ => a
+            .children()
+            .iter()
+            .zip(b.children())
+            .try_for_each(|(&ca, &cb)| same_value(ea, ca, eb, cb, rng)),
+    }
+}
+
+/// Compare two ground terms. Free loop variables are given random values.
+pub fn equivalent(a: &RecExpr<Cad>, b: &RecExpr<Cad>, rng: &mut Pcg64) -> Verdict {
+    let mut ctx = HashMap::new();
+    for var in ["i", "j", "k"] {
+        ctx.insert(var, rng.gen_range(0, 4) as f64);
+    }
+    let na = quietly(|| normalize(a, &ctx));
+    let nb = quietly(|| normalize(b, &ctx));
+    match (na, nb) {
+        // a number left unevaluated, like a division by zero, has no solid
+        (Some((ea, pa)), Some((eb, pb))) => match quietly(|| same_value(&ea, pa, &eb, pb, rng)) {
+            Some(Ok(())) => Verdict::Same,
+            Some(Err(why)) => Verdict::Different(why),
+            None => Verdict::Unknown,
+        },
+        _ => Verdict::Unknown,
+    }
+}
+
+/// What a pattern variable stands for, guessed from where it occurs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Num,
+    PosNum,
+    Nat,
+    Bool,
+    Vec,
+    PosVec,
+    /// rotation angles, also outside of -180 to 180
+    Angles,
+    Cad,
+    AffOp,
+    BoolOp,
+    Perm,
+    Part,
+    VecList,
+    CadList,
+    VecLists,
+    CadLists,
+    /// lists one element longer, for a Remove to drop one
+    LongVecList,
+    LongCadList,
+    Indices,
+    /// the bound of a MapI, a count or a Range
+    Bound,
+    /// the two bounds of a MapI over a grid or a staircase, which count to the list length
+    Rows,
+    Cols,
+    /// a vector in the loop variables, for the body of a MapI
+    Formula,
+    GridFormula,
+}
+
+fn elem(sort: Kind) -> Kind {
+    match sort {
+        Kind::VecList => Kind::Vec,
+        Kind::VecLists => Kind::VecList,
+        Kind::CadLists => Kind::CadList,
+        _ => Kind::Cad,
+    }
+}
+
+fn lists_of(sort: Kind) -> Kind {
+    match sort {
+        Kind::VecList => Kind::VecLists,
+        _ => Kind::CadLists,
+    }
+}
+
+fn child_kinds(node: &Cad, expected: Kind) -> Vec<Kind> {
+    let n = node.children().len();
+    match node {
+        Cad::Add(_) | Cad::Sub(_) | Cad::Mul(_) | Cad::Div(_) | Cad::Pow(_) | Cad::Mod(_) => {
+            vec![Kind::Num; 2]
+        }
+        Cad::Sin(_) | Cad::Cos(_) => vec![Kind::Num],
+        Cad::Sqrt(_) => vec![Kind::PosNum],
+        Cad::Vec3(_) if expected == Kind::PosVec => vec![Kind::PosNum; 3],
+        Cad::Vec3(_) => vec![Kind::Num; 3],
+        Cad::Cube(_) => vec![Kind::PosVec, Kind::Bool],
+        Cad::Sphere(_) => vec![Kind::PosNum, Kind::Vec],
+        Cad::Cylinder(_) => vec![Kind::PosVec, Kind::Vec, Kind::Bool],
+        Cad::Hull(_) => vec![Kind::CadList],
+        Cad::Affine(_) => vec![Kind::AffOp, Kind::Vec, expected],
+        Cad::Binop(_) => vec![Kind::BoolOp, Kind::Cad, Kind::Cad],
+        Cad::Fold(_) => vec![Kind::BoolOp, Kind::CadList],
+        Cad::Map2(_) => vec![Kind::AffOp, Kind::VecList, Kind::CadList],
+        Cad::Iterate(_) => vec![Kind::Nat, Kind::AffOp, Kind::Vec, Kind::CadList],
+        Cad::Repeat(_) => vec![Kind::Nat, elem(expected)],
+        Cad::Range(_) => vec![Kind::Num, Kind::Num, Kind::Nat],
+        Cad::MapI(_) => {
+            let mut sorts = match n - 1 {
+                1 => vec![Kind::Bound],
+                2 => vec![Kind::Rows, Kind::Cols],
+                bounds => vec![Kind::Nat; bounds],
+            };
+            sorts.push(match (expected, n - 1) {
+                (Kind::VecList, 1) => Kind::Formula,
+                (Kind::VecList, 2) => Kind::GridFormula,
+                _ => elem(expected),
+            });
+            sorts
+        }
+        Cad::List(_) => vec![elem(expected); n],
+        Cad::Cons(_) => vec![elem(expected), expected],
+        Cad::Concat(_) => vec![lists_of(expected)],
+        Cad::Sort(_) | Cad::Unsort(_) => vec![Kind::Perm, expected],
+        Cad::Part(_) => vec![Kind::Part, elem(expected)],
+        Cad::Unpart(_) => vec![Kind::Part, lists_of(expected)],
+        Cad::Unpolar(_) => vec![Kind::Nat, Kind::Vec, Kind::VecList],
+        Cad::Remove(_) if expected == Kind::VecList => vec![Kind::LongVecList, Kind::Indices],
+        Cad::Remove(_) => vec![Kind::LongCadList, Kind::Indices],
+        _ => vec![Kind::Cad; n],
+    }
+}
+
+/// Random ground terms. All lists of an instance share one length,
+/// and all partitionings and grids are the same, so that the pieces fit together.
+struct Gen {
+    rng: Pcg64,
+    len: usize,
+    part: Partitioning,
+    // a grid of rows by len / rows, or rows of 1, 2, .. elements
+    rows: usize,
+    staircase: bool,
+}
+
+impl Gen {
+    fn new(seed: u64) -> Gen {
+        let mut rng = Pcg64::seed_from_u64(seed);
+        let len = *[3, 4, 6].choose(&mut rng).unwrap();
+        let mut lengths = vec![];
+        let mut left = len;
+        while left > 0 {
+            let l = rng.gen_range(1, left + 1);
+            lengths.push(l);
+            left -= l;
+        }
+        let part = Partitioning::from_vec(lengths);
+
+        let mut grids: Vec<(usize, bool)> = (1..=len)
+            .filter(|rows| len % rows == 0)
+            .map(|rows| (rows, false))
+            .collect();
+        // 3 = 1 + 2 and 6 = 1 + 2 + 3
+        grids.extend(
+            (2..len)
+                .filter(|r| r * (r + 1) / 2 == len)
+                .map(|r| (r, true)),
+        );
+        let (rows, staircase) = *grids.choose(&mut rng).unwrap();
+        Gen {
+            rng,
+            len,
+            part,
+            rows,
+            staircase,
+        }
+    }
+
+    fn root_kind(&mut self, node: &Cad) -> Kind {
+        match node {
+            Cad::Add(_) | Cad::Sub(_) | Cad::Mul(_) | Cad::Div(_) | Cad::Pow(_) | Cad::Mod(_) => {
+                Kind::Num
+            }
+            Cad::Sin(_) | Cad::Cos(_) | Cad::Sqrt(_) | Cad::Pi => Kind::Num,
+            Cad::Vec3(_) => Kind::Vec,
+            Cad::Map2(_) | Cad::Iterate(_) => Kind::CadList,
+            Cad::Unpolar(_) => Kind::VecList,
+            Cad::Part(_) => *[Kind::VecLists, Kind::CadLists]
+                .choose(&mut self.rng)
+                .unwrap(),
+            Cad::Repeat(_)
+            | Cad::MapI(_)
+            | Cad::List(_)
+            | Cad::Cons(_)
+            | Cad::Concat(_)
+            | Cad::Sort(_)
+            | Cad::Unsort(_)
+            | Cad::Unpart(_)
+            | Cad::Remove(_) => *[Kind::VecList, Kind::CadList]
+                .choose(&mut self.rng)
+                .unwrap(),
+            _ => Kind::Cad,
+        }
+    }
+
+    fn num(&mut self, out: &mut RecExpr<Cad>) -> Id {
+        let n = *[-3.0, -2.0, -1.0, 0.0, 0.5, 1.0, 2.0, 3.0]
+            .choose(&mut self.rng)
+            .unwrap();
+        out.add(Cad::Num(n.into()))
+    }
+
+    fn pos_num(&mut self, out: &mut RecExpr<Cad>) -> Id {
+        let n = *[0.5, 1.0, 2.0, 3.0].choose(&mut self.rng).unwrap();
+        out.add(Cad::Num(n.into()))
+    }
+
+    fn vec(&mut self, pos: bool, out: &mut RecExpr<Cad>) -> Id {
+        let mut component = |g: &mut Gen| if pos { g.pos_num(out) } else { g.num(out) };
+        let v = [component(self), component(self), component(self)];
+        out.add(Cad::Vec3(v))
+    }
+
+    fn angles(&mut self, out: &mut RecExpr<Cad>) -> Id {
+        let mut angle = |g: &mut Gen| {
+            let a = *[-270.0, -90.0, 0.0, 0.0, 30.0, 90.0, 200.0, 450.0]
+                .choose(&mut g.rng)
+                .unwrap();
+            out.add(Cad::Num(a.into()))
+        };
+        let v = [angle(self), angle(self), angle(self)];
+        out.add(Cad::Vec3(v))
+    }
+
+    fn param(&mut self, op: &Cad, out: &mut RecExpr<Cad>) -> Id {
+        match op {
+            Cad::Scale => self.vec(true, out),
+            Cad::Rotate => self.angles(out),
+            _ => self.vec(false, out),
+        }
+    }
+
+    // a vector that is constant, linear or, in a single variable, geometric
+    fn formula(&mut self, vars: &[&'static str], out: &mut RecExpr<Cad>) -> Id {
+        let vars: Vec<Id> = vars
+            .iter()
+            .map(|&v| out.add(Cad::ListVar(ListVar(v))))
+            .collect();
+        let kind = self.rng.gen_range(0, 3);
+        let mut component = |g: &mut Gen| match kind {
+            0 => g.num(out),
+            1 => vars.iter().fold(g.num(out), |acc, &v| {
+                let a = g.num(out);
+                let av = out.add(Cad::Mul([a, v]));
+                out.add(Cad::Add([acc, av]))
+            }),
+            _ => {
+                let s = *[0.5, 1.0, 2.0, 3.0].choose(&mut g.rng).unwrap();
+                let s = out.add(Cad::Num(s.into()));
+                out.add(Cad::Pow([s, vars[0]]))
+            }
+        };
+        let v = [component(self), component(self), component(self)];
+        out.add(Cad::Vec3(v))
+    }
+
+    fn bound(&mut self, out: &mut RecExpr<Cad>) -> Id {
+        let n = out.add(Cad::Num(self.len.into()));
+        if self.rng.gen_range(0, 3) > 0 {
+            return n;
+        }
+        let start = self.num(out);
+        let step = *[-2.0, -1.0, 1.0, 2.0].choose(&mut self.rng).unwrap();
+        let step = out.add(Cad::Num(step.into()));
+        out.add(Cad::Range([start, step, n]))
+    }
+
+    fn cols(&mut self, out: &mut RecExpr<Cad>) -> Id {
+        if self.staircase {
+            let one = out.add(Cad::Num(1.into()));
+            let i = out.add(Cad::ListVar(ListVar("i")));
+            out.add(Cad::Add([one, i]))
+        } else {
+            out.add(Cad::Num((self.len / self.rows).into()))
+        }
+    }
+
+    // a list of one more element than the others, for a Remove
+    fn long_list(&mut self, sort: Kind, out: &mut RecExpr<Cad>) -> Id {
+        self.len += 1;
+        let list = self.list(sort, out);
+        self.len -= 1;
+        list
+    }
+
+    // the vectors start + i * step, which loop inference likes
+    fn progression(&mut self, out: &mut RecExpr<Cad>) -> Vec<Id> {
+        let pick = |g: &mut Gen| *[-1.0, 0.0, 1.0, 2.0].choose(&mut g.rng).unwrap();
+        let start = (pick(self), pick(self), pick(self));
+        let step = (pick(self), pick(self), pick(self));
+        (0..self.len)
+            .map(|i| {
+                let i = i as f64;
+                let x = out.add(Cad::Num((start.0 + i * step.0).into()));
+                let y = out.add(Cad::Num((start.1 + i * step.1).into()));
+                let z = out.add(Cad::Num((start.2 + i * step.2).into()));
+                out.add(Cad::Vec3([x, y, z]))
+            })
+            .collect()
+    }
+
+    fn cad(&mut self, depth: usize, out: &mut RecExpr<Cad>) -> Id {
+        let choice = if depth == 0 {
+            self.rng.gen_range(0, 4)
+        } else {
+            self.rng.gen_range(0, 7)
+        };
+        let zero = out.add(Cad::Num(0.into()));
+        let params = out.add(Cad::Vec3([zero, zero, zero]));
+        match choice {
+            0 => {
+                let size = self.vec(true, out);
+                let center = out.add(Cad::Bool(self.rng.gen()));
+                out.add(Cad::Cube([size, center]))
+            }
+            1 => {
+                let r = self.pos_num(out);
+                out.add(Cad::Sphere([r, params]))
+            }
+            2 => {
+                let size = self.vec(true, out);
+                let center = out.add(Cad::Bool(self.rng.gen()));
+                out.add(Cad::Cylinder([size, params, center]))
+            }
+            3 if self.rng.gen_range(0, 4) == 0 => out.add(Cad::Empty),
+            3 => {
+                let size = self.vec(true, out);
+                let center = out.add(Cad::Bool(true));
+                out.add(Cad::Cube([size, center]))
+            }
+            4 | 5 => {
+                let op = self.term(Kind::AffOp, out);
+                let param = self.param(&out[op].clone(), out);
+                let cad = self.cad(depth - 1, out);
+                out.add(Cad::Affine([op, param, cad]))
+            }
+            _ => {
+                let op = self.term(Kind::BoolOp, out);
+                let a = self.cad(depth - 1, out);
+                let b = self.cad(depth - 1, out);
+                out.add(Cad::Binop([op, a, b]))
+            }
+        }
+    }
+
+    fn list(&mut self, sort: Kind, out: &mut RecExpr<Cad>) -> Id {
+        let shape = self.rng.gen_range(0, 9);
+        let zero = out.add(Cad::Num(0.into()));
+        let ids = match sort {
+            Kind::VecList | Kind::CadList if shape == 0 => {
+                let long = if sort == Kind::VecList {
+                    Kind::LongVecList
+                } else {
+                    Kind::LongCadList
+                };
+                let list = self.term(long, out);
+                let indices = self.term(Kind::Indices, out);
+                return out.add(Cad::Remove([list, indices]));
+            }
+            Kind::VecList if shape == 1 => {
+                let bound = self.bound(out);
+                let formula = self.formula(&["i"], out);
+                return out.add(Cad::MapI(vec![bound, formula]));
+            }
+            Kind::CadList if shape == 1 => {
+                let n = out.add(Cad::Num(self.len.into()));
+                let op = [Cad::Trans, Cad::Scale, Cad::Rotate].choose(&mut self.rng);
+                let op = op.unwrap().clone();
+                let param = self.param(&op, out);
+                let op = out.add(op);
+                let cads = self.list(Kind::CadList, out);
+                return out.add(Cad::Iterate([n, op, param, cads]));
+            }
+            // a row of cubes that share faces
+            Kind::CadList if shape == 6 => {
+                let width = *[0.5, 1.0, 2.0, 3.0].choose(&mut self.rng).unwrap();
+                let x = out.add(Cad::Num(width.into()));
+                let (y, z) = (self.pos_num(out), self.pos_num(out));
+                let size = out.add(Cad::Vec3([x, y, z]));
+                let corner = out.add(Cad::Bool(false));
+                let cube = out.add(Cad::Cube([size, corner]));
+                let trans = out.add(Cad::Trans);
+                (0..self.len)
+                    .map(|i| {
+                        let x = out.add(Cad::Num((width * i as f64).into()));
+                        let v = out.add(Cad::Vec3([x, zero, zero]));
+                        out.add(Cad::Affine([trans, v, cube]))
+                    })
+                    .collect()
+            }
+            // folds of every operator, for the rules that flatten nested folds
+            Kind::CadList if shape == 8 => {
+                let ops = [Cad::Union, Cad::Inter, Cad::Diff];
+                let first = self.rng.gen_range(0, ops.len());
+                (0..self.len)
+                    .map(|i| {
+                        let op = out.add(ops[(first + i) % ops.len()].clone());
+                        let cads = (0..2).map(|_| self.cad(1, out)).collect();
+                        let cads = out.add(Cad::List(cads));
+                        out.add(Cad::Fold([op, cads]))
+                    })
+                    .collect()
+            }
+            Kind::VecList if shape < 6 => self.progression(out),
+            Kind::CadList if shape < 6 => {
+                let cad = self.cad(1, out);
+                let trans = out.add(Cad::Trans);
+                self.progression(out)
+                    .into_iter()
+                    .map(|v| out.add(Cad::Affine([trans, v, cad])))
+                    .collect()
+            }
+            Kind::VecLists | Kind::CadLists => {
+                let len = self.len;
+                let lengths = self.part.lengths.clone();
+                let ids = lengths
+                    .into_iter()
+                    .map(|l| {
+                        self.len = l;
+                        self.list(elem(sort), out)
+                    })
+                    .collect();
+                self.len = len;
+                ids
+            }
+            _ => (0..self.len).map(|_| self.term(elem(sort), out)).collect(),
+        };
+        out.add(Cad::List(ids))
+    }
+
+    fn term(&mut self, sort: Kind, out: &mut RecExpr<Cad>) -> Id {
+        match sort {
+            Kind::Num => self.num(out),
+            Kind::PosNum => self.pos_num(out),
+            Kind::Nat => out.add(Cad::Num(self.len.into())),
+            Kind::Bool => out.add(Cad::Bool(self.rng.gen())),
+            Kind::Vec => self.vec(false, out),
+            Kind::PosVec => self.vec(true, out),
+            Kind::Angles => self.angles(out),
+            Kind::Cad => self.cad(2, out),
+            Kind::AffOp => {
+                let op = [Cad::Trans, Cad::Scale, Cad::Rotate, Cad::Mirror].choose(&mut self.rng);
+                out.add(op.unwrap().clone())
+            }
+            Kind::BoolOp => {
+                let op = [Cad::Union, Cad::Inter, Cad::Diff].choose(&mut self.rng);
+                out.add(op.unwrap().clone())
+            }
+            Kind::Perm => {
+                let mut order: Vec<usize> = (0..self.len).collect();
+                order.shuffle(&mut self.rng);
+                out.add(Cad::Permutation(Permutation::from_vec(&order)))
+            }
+            Kind::Part => out.add(Cad::Partitioning(self.part.clone())),
+            Kind::Indices => {
+                // into a list one longer, see LongVecList
+                let i = out.add(Cad::Num(self.rng.gen_range(0, self.len + 1).into()));
+                out.add(Cad::List(vec![i]))
+            }
+            Kind::LongVecList => self.long_list(Kind::VecList, out),
+            Kind::LongCadList => self.long_list(Kind::CadList, out),
+            Kind::Bound => self.bound(out),
+            Kind::Rows => out.add(Cad::Num(self.rows.into())),
+            Kind::Cols => self.cols(out),
+            Kind::Formula => self.formula(&["i"], out),
+            Kind::GridFormula => self.formula(&["i", "j"], out),
+            Kind::VecList | Kind::CadList | Kind::VecLists | Kind::CadLists => self.list(sort, out),
+        }
+    }
+
+    fn instantiate(&mut self, ast: &PatternAst<Cad>) -> RecExpr<Cad> {
+        let nodes = ast.as_ref();
+        let mut sorts: Vec<Option<Kind>> = vec![None; nodes.len()];
+        let root = nodes.len() - 1;
+        sorts[root] = Some(match &nodes[root] {
+            ENodeOrVar::ENode(n) => self.root_kind(n),
+            ENodeOrVar::Var(_) => Kind::CadList,
+        });
+        // children come before their parents
+        for i in (0..nodes.len()).rev() {
+            if let (ENodeOrVar::ENode(n), Some(sort)) = (&nodes[i], sorts[i]) {
+                let mut kinds = child_kinds(n, sort);
+                // the angles of a rotation
+                let op_param = match n {
+                    Cad::Affine([op, ..]) => Some((*op, 1)),
+                    Cad::Iterate([_, op, ..]) => Some((*op, 2)),
+                    _ => None,
+                };
+                if let Some((op, k)) = op_param {
+                    if let ENodeOrVar::ENode(Cad::Rotate) = nodes[usize::from(op)] {
+                        kinds[k] = Kind::Angles;
+                    }
+                }
+                for (&c, s) in n.children().iter().zip(kinds) {
+                    let c = usize::from(c);
+                    sorts[c] = sorts[c].or(Some(s));
+                }
+            }
+        }
+
+        let mut out = RecExpr::from(vec![]);
+        let mut vars: HashMap<Var, Id> = HashMap::new();
+        let mut ids: Vec<Id> = vec![];
+        for (node, sort) in nodes.iter().zip(sorts) {
+            let id = match node {
+                ENodeOrVar::ENode(n) => out.add(n.clone().map_children(|c| ids[usize::from(c)])),
+                ENodeOrVar::Var(v) => match vars.get(v) {
+                    Some(&id) => id,
+                    None => {
+                        let id = self.term(sort.unwrap_or(Kind::Cad), &mut out);
+                        vars.insert(*v, id);
+                        id
+                    }
+                },
+            };
+            ids.push(id);
+        }
+        out
+    }
+}
+
+// Apply the rule once and return both sides of each union it made,
+// or `None` if it did not match.
+fn apply_once(
+    rule: &Rewrite,
+    instance: &RecExpr<Cad>,
+) -> Option<Vec<(RecExpr<Cad>, RecExpr<Cad>)>> {
+    let mut egraph = EGraph::new(MetaAnalysis).with_explanations_enabled();
+    egraph.add_expr(instance);
+    egraph.rebuild();
+    let matches = rule.search(&egraph);
+    if matches.is_empty() {
+        return None;
+    }
+    rule.apply(&mut egraph, &matches);
+    egraph.rebuild();
+    let pairs = egraph
+        .get_union_equalities()
+        .into_iter()
+        .filter(|(_, _, reason)| *reason == rule.name)
+        .map(|(a, b, _)| (egraph.id_to_expr(a), egraph.id_to_expr(b)))
+        .collect();
+    Some(pairs)
+}
+
+pub fn check_rule(rule: &Rewrite, instances: usize, seed: u64) -> RuleReport {
+    let mut report = RuleReport {
+        name: rule.name.to_string(),
+        ..Default::default()
+    };
+    let ast = match rule.searcher.get_pattern_ast() {
+        Some(ast) => ast,
+        None => return report,
+    };
+    for n in 0..instances as u64 {
+        let mut gen = Gen::new(seed.wrapping_add(n));
+        let instance = gen.instantiate(ast);
+        let pairs = match quietly(|| apply_once(rule, &instance)).flatten() {
+            Some(pairs) => pairs,
+            None => continue,
+        };
+        report.fired += 1;
+        for (lhs, rhs) in pairs {
+            match equivalent(&lhs, &rhs, &mut gen.rng) {
+                Verdict::Same => report.checked += 1,
+                Verdict::Unknown => report.unchecked += 1,
+                Verdict::Different(why) => {
+                    report.checked += 1;
+                    report.mismatches.push(describe(&lhs, &rhs, &why));
+                }
+            }
+        }
+    }
+    report
+}
+
+pub fn check_rules(rules: &[Rewrite]) -> Vec<RuleReport> {
+    rules
+        .iter()
+        .map(|rule| check_rule(rule, CHECK_INSTANCES, CHECK_SEED))
+        .collect()
+}
+
+/// Re-validate up to `sample` of the unions rules made in `egraph`,
+/// which must have explanations enabled.
+pub fn trace_unions(egraph: &EGraph, sample: usize) -> UnionTrace {
+    let mut trace = UnionTrace::default();
+    let equalities = egraph.get_union_equalities();
+    for (_, _, rule) in &equalities {
+        *trace.unions.entry(rule.to_string()).or_default() += 1;
+    }
+
+    let mut rng = Pcg64::seed_from_u64(CHECK_SEED);
+    for (a, b, rule) in equalities.choose_multiple(&mut rng.clone(), sample) {
+        let (lhs, rhs) = (egraph.id_to_expr(*a), egraph.id_to_expr(*b));
+        match equivalent(&lhs, &rhs, &mut rng) {
+            Verdict::Same => trace.checked += 1,
+            Verdict::Unknown => trace.unchecked += 1,
+            Verdict::Different(why) => {
+                trace.checked += 1;
+                let why = format!("{}: {}", rule, why);
+                trace.mismatches.push(describe(&lhs, &rhs, &why));
+            }
+        }
+    }
+    trace
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{pre_rules, rules};
+
+    // Rules that fire but never make a union to compare. Constant folding already merges
+    // both sides of the arithmetic identities, and the others only rewrite shapes that
+    // random ground terms do not have.
+    const UNCHECKED: &[&str] = &[
+        "add_zero",
+        "sub_zero",
+        "mul_zero",
+        "mul_one",
+        "mul_div",
+        "div_one",
+        "div_mul",
+        "unpart-unsort",
+    ];
+
+    #[test]
+    fn rules_are_sound() {
+        let mut all = pre_rules();
+        all.extend(rules());
+        let reports = check_rules(&all);
+
+        let unsound: Vec<_> = reports
+            .iter()
+            .filter(|r| !r.mismatches.is_empty())
+            .collect();
+        assert!(unsound.is_empty(), "unsound rules: {:#?}", unsound);
+
+        // a rule that is never compared passes vacuously
+        let idle: Vec<_> = reports
+            .iter()
+            .filter(|r| r.fired == 0 || (r.checked == 0 && !UNCHECKED.contains(&&*r.name)))
+            .map(|r| &r.name)
+            .collect();
+        assert!(idle.is_empty(), "rules never checked: {:?}", idle);
+    }
+}
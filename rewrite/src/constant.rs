@@ -0,0 +1,189 @@
+/// Constant
+/// Short closed forms for long numbers, like `(/ 100 3)` for 33.333333 or
+/// `(/ (sqrt 2) 2)` for 0.707107. The forms are added to the e-graph as arithmetic, and the
+/// cost model picks them over the digits.
+use std::f64::consts::PI;
+
+use egg::Id;
+
+use crate::base::num::Num;
+use crate::cad::{Cad, EGraph};
+use crate::hyperparameters::{
+    CONST_MAX_DENOM, CONST_MAX_NUMER, CONST_MAX_PARTS, CONST_TOLERANCE, NUM_FREE_DIGITS,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Factor {
+    One,
+    Sqrt(i64),
+    Pi,
+}
+
+impl Factor {
+    fn value(self) -> f64 {
+        match self {
+            Factor::One => 1.0,
+            Factor::Sqrt(k) => (k as f64).sqrt(),
+            Factor::Pi => PI,
+        }
+    }
+}
+
+/// The number `p / q * factor`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Form {
+    pub p: i64,
+    pub q: i64,
+    pub factor: Factor,
+}
+
+impl Form {
+    pub fn value(&self) -> f64 {
+        self.p as f64 / self.q as f64 * self.factor.value()
+    }
+}
+
+fn is_close(x: f64, v: f64) -> bool {
+    (x - v).abs() <= CONST_TOLERANCE * v.abs().max(1.0)
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// The simplest form of `x`: a small fraction, of 1 first, then of a square root, then of
+/// pi. Failing those, a whole turn divided into parts.
+pub fn closed_form(x: f64) -> Option<Form> {
+    if !x.is_finite() {
+        return None;
+    }
+    let factors = [
+        Factor::One,
+        Factor::Sqrt(2),
+        Factor::Sqrt(3),
+        Factor::Sqrt(5),
+        Factor::Sqrt(6),
+        Factor::Pi,
+    ];
+    for factor in factors {
+        let y = x / factor.value();
+        for q in 1..=CONST_MAX_DENOM {
+            let p = (y * q as f64).round();
+            // the numerator only grows with the denominator
+            if p.abs() > CONST_MAX_NUMER as f64 {
+                break;
+            }
+            let p = p as i64;
+            // a fraction that is not in lowest terms was tried with a smaller q
+            if p == 0 || gcd(p.abs(), q) != 1 {
+                continue;
+            }
+            let form = Form { p, q, factor };
+            if is_close(x, form.value()) {
+                return Some(form);
+            }
+        }
+    }
+    (1..=CONST_MAX_PARTS)
+        .map(|n| Form {
+            p: 360,
+            q: n,
+            factor: Factor::One,
+        })
+        .find(|form| is_close(x, form.value()))
+}
+
+fn add_int(egraph: &mut EGraph, n: i64) -> Id {
+    egraph.add(Cad::Num((n as f64).into()))
+}
+
+/// Add the form as arithmetic. Its e-class has the value of the form as its best term.
+pub fn add_form(egraph: &mut EGraph, form: Form) -> Id {
+    let p = add_int(egraph, form.p);
+    let factor = match form.factor {
+        Factor::One => None,
+        Factor::Sqrt(k) => {
+            let k = add_int(egraph, k);
+            Some(egraph.add(Cad::Sqrt([k])))
+        }
+        Factor::Pi => Some(egraph.add(Cad::Pi)),
+    };
+    let top = match (form.p, factor) {
+        (_, None) => p,
+        (1, Some(f)) => f,
+        (_, Some(f)) => egraph.add(Cad::Mul([p, f])),
+    };
+    if form.q == 1 {
+        top
+    } else {
+        let q = add_int(egraph, form.q);
+        egraph.add(Cad::Div([top, q]))
+    }
+}
+
+/// Whether the number is long enough for a closed form to pay off.
+pub fn is_long(n: &Num) -> bool {
+    n.significant_digits() > NUM_FREE_DIGITS
+}
+
+/// Add closed forms for the long numbers in the e-graph, in the e-classes of the numbers.
+/// Returns the number of unions made. The e-graph is rebuilt only if a form was new, since
+/// that leaves it dirty.
+pub fn recognize_constants(egraph: &mut EGraph) -> usize {
+    let nums: Vec<(Num, Id)> = egraph
+        .classes()
+        .filter_map(|c| match &c.data.best {
+            Cad::Num(n) if is_long(n) => Some((n.clone(), c.id)),
+            _ => None,
+        })
+        .collect();
+
+    let mut unions = 0;
+    for (n, id) in nums {
+        if let Some(form) = closed_form(n.to_f64()) {
+            let form = add_form(egraph, form);
+            if egraph.find(form) != egraph.find(id) {
+                egraph.union_trusted(id, form, "constant");
+                unions += 1;
+            }
+        }
+    }
+
+    if !egraph.clean {
+        egraph.rebuild();
+    }
+    unions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(p: i64, q: i64, factor: Factor) -> Option<Form> {
+        Some(Form { p, q, factor })
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn finds_closed_forms() {
+        assert_eq!(closed_form(33.333333), form(100, 3, Factor::One));
+        assert_eq!(closed_form(0.7071067811), form(1, 2, Factor::Sqrt(2)));
+        assert_eq!(closed_form(17.320508), form(10, 1, Factor::Sqrt(3)));
+        assert_eq!(closed_form(6.2831853), form(2, 1, Factor::Pi));
+        assert_eq!(closed_form(27.692308), form(360, 13, Factor::One));
+        assert_eq!(closed_form(9.9999999), form(10, 1, Factor::One));
+        assert_eq!(closed_form(12.3457), None);
+    }
+
+    #[test]
+    fn long_numbers() {
+        assert!(is_long(&Num::from(33.333333)));
+        assert!(is_long(&Num::from(10000.5)));
+        assert!(!is_long(&Num::from(25000.0)));
+        assert!(!is_long(&Num::from(0.0125)));
+    }
+}
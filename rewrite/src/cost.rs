@@ -2,7 +2,7 @@ use egg::{Id, Language};
 
 use crate::{
     cad::Cad,
-    hyperparameters::{COST_BIG_VALUE, COST_SMALL_VALUE},
+    hyperparameters::{COST_BIG_VALUE, COST_SMALL_VALUE, NUM_DIGIT_COST, NUM_FREE_DIGITS},
 };
 
 pub type Cost = f64;
@@ -20,22 +20,29 @@ impl egg::CostFunction<Cad> for CostFn {
         let cost = match enode {
             Num(n) => {
                 let s = format!("{}", n);
-                1.0 + (0.000001 * s.len() as Cost)
+                // long numbers cost more than a short closed form like (/ 100 3)
+                let long = n.significant_digits().saturating_sub(NUM_FREE_DIGITS);
+                1.0 + (0.000001 * s.len() as Cost) + NUM_DIGIT_COST * long as Cost
             }
             Bool(_) | ListVar(_) => COST_SMALL_VALUE,
             Add(_args) | Sub(_args) | Mul(_args) | Div(_args) => COST_SMALL_VALUE,
+            Pow(_args) | Mod(_args) => COST_SMALL_VALUE,
+            Sin(_args) | Cos(_args) | Sqrt(_args) => COST_SMALL_VALUE,
+            Pi => 1.0,
 
             BlackBox(..) => 1.0,
             Cube(_) | Empty | Nil | Sphere(_) | Cylinder(_) | Hull(_) => 1.0,
 
-            Trans | TransPolar | Scale | Rotate => 1.0,
+            Trans | TransPolar | Scale | Rotate | Mirror => 1.0,
 
             Union | Diff | Inter => 1.0,
 
             Repeat(_) => 0.99,
             MapI(_) => 1.0,
+            Range(_) => COST_SMALL_VALUE,
             Fold(_) => 1.0,
             Map2(_) => 1.0,
+            Iterate(_) => 1.0,
             Affine(_) => 1.0,
             Binop(_) => 1.0,
 
@@ -45,11 +52,16 @@ impl egg::CostFunction<Cad> for CostFn {
             Vec3(_) => 1.0,
 
             Unpolar(_) => COST_BIG_VALUE,
+            Remove(_) => 1.0,
             Sort(_) | Unsort(_) | Part(_) | Unpart(_) => COST_BIG_VALUE,
             Partitioning(_) => COST_BIG_VALUE,
             Permutation(_) => COST_BIG_VALUE,
         };
 
+        // the count of an iterate is the length of the list it maps over, which is charged there
+        if let Iterate([_, op, param, cads]) = enode {
+            return cost + costs(*op) + costs(*param) + costs(*cads);
+        }
         enode.fold(cost, |sum, i| sum + costs(i))
     }
 }
@@ -2,7 +2,8 @@ use std::collections::HashMap;
 
 use egg::{Id, Language, RecExpr};
 
-use crate::base::geom::to_cartesian;
+use crate::base::geom::{to_cartesian, Affine3};
+use crate::base::num::Num;
 use crate::cad::Cad;
 use crate::cad_struct::{get_num, get_vec3_nums};
 
@@ -12,7 +13,7 @@ pub fn eval(cx: Option<&FunCtx>, expr: &RecExpr<Cad>, p: Id, out: &mut RecExpr<C
     eval_(cx, expr, p, out)
 }
 
-type FunCtx = HashMap<&'static str, usize>;
+type FunCtx = HashMap<&'static str, f64>;
 
 fn mk_vec((x, y, z): (f64, f64, f64), out: &mut RecExpr<Cad>) -> Id {
     let x = out.add(Cad::Num(x.into()));
@@ -25,6 +26,19 @@ fn mk_list(exprs: Vec<Id>) -> Cad {
     Cad::List(exprs)
 }
 
+// The count of an Iterate or Unpolar repeats the length of its list, so it has to agree.
+fn check_count(
+    cx: Option<&FunCtx>,
+    expr: &RecExpr<Cad>,
+    (op, n): (&str, Id),
+    len: usize,
+    out: &mut RecExpr<Cad>,
+) {
+    let n = eval(cx, expr, n, out);
+    let n = get_num(out, n);
+    assert!(n == len as f64, "{} of {} over a list of {}", op, n, len);
+}
+
 fn get_list(expr: &RecExpr<Cad>, list: Id) -> &Vec<Id> {
     match &expr[list] {
         Cad::List(list) => list,
@@ -40,6 +54,85 @@ fn eval_list(cx: Option<&FunCtx>, expr: &RecExpr<Cad>, p: Id, out: &mut RecExpr<
     }
 }
 
+// Loop variables are bound outermost first, and each bound may use the variables outside it.
+fn tabulate(
+    ctx: &mut FunCtx,
+    vars: &[&'static str],
+    bounds: &[Id],
+    expr: &RecExpr<Cad>,
+    body: Id,
+    out: &mut RecExpr<Cad>,
+    results: &mut Vec<Id>,
+) {
+    match bounds.split_first() {
+        None => results.push(eval(Some(ctx), expr, body, out)),
+        Some((&bound, rest)) => {
+            let mut num = |id: Id| {
+                let id = eval(Some(ctx), expr, id, out);
+                get_num(out, id)
+            };
+            let (start, step, n) = match expr[bound] {
+                Cad::Range([start, step, n]) => (num(start), num(step), num(n)),
+                _ => (0.0, 1.0, num(bound)),
+            };
+            for x in 0..n.round().max(0.0) as usize {
+                ctx.insert(vars[0], start + step * x as f64);
+                tabulate(ctx, &vars[1..], rest, expr, body, out, results);
+            }
+        }
+    }
+}
+
+// An affine applied to a list applies to each element.
+fn affine(aff: Id, param: Id, cad: Id, out: &mut RecExpr<Cad>) -> Id {
+    if let Cad::List(list) = &out[cad] {
+        let list = list
+            .clone()
+            .into_iter()
+            .map(|c| affine(aff, param, c, out))
+            .collect();
+        return out.add(mk_list(list));
+    }
+    // an affine applied to a position moves the point, like a rotated Unpolar
+    if let Cad::Vec3(_) = out[cad] {
+        let v = get_vec3_nums(out, param);
+        let m = match out[aff] {
+            Cad::Trans => Affine3::translate(v),
+            Cad::TransPolar => Affine3::translate(to_cartesian(v)),
+            Cad::Scale => Affine3::scale(v),
+            Cad::Rotate => Affine3::rotate(v),
+            Cad::Mirror => Affine3::mirror(v).unwrap_or_else(Affine3::identity),
+            _ => panic!("expected affine kind, got {:?}", aff),
+        };
+        let p = m.apply(get_vec3_nums(out, cad));
+        return mk_vec(p, out);
+    }
+    match out[aff] {
+        Cad::Trans | Cad::Scale | Cad::Rotate | Cad::Mirror => {
+            out.add(Cad::Affine([aff, param, cad]))
+        }
+        Cad::TransPolar => {
+            let pnums = get_vec3_nums(out, param);
+            let cnums = to_cartesian(pnums);
+
+            let trans = out.add(Cad::Trans);
+            let cnums = mk_vec(cnums, out);
+            out.add(Cad::Affine([trans, cnums, cad]))
+        }
+        _ => panic!("expected affine kind, got {:?}", aff),
+    }
+}
+
+// The number, or the operation left unevaluated when it has no finite value, like a
+// negative base to a fractional power. Constant folding in cad.rs does not fold those either.
+fn num_or(out: &mut RecExpr<Cad>, v: f64, op: Cad) -> Id {
+    if v.is_finite() {
+        out.add(Cad::Num(v.into()))
+    } else {
+        out.add(op)
+    }
+}
+
 fn eval_(cx: Option<&FunCtx>, expr: &RecExpr<Cad>, p: Id, out: &mut RecExpr<Cad>) -> Id {
     let e = expr[p].clone();
     match &e {
@@ -76,8 +169,36 @@ fn eval_(cx: Option<&FunCtx>, expr: &RecExpr<Cad>, p: Id, out: &mut RecExpr<Cad>
             let args = args.map(|arg| eval(cx, expr, arg, out));
             let a = get_num(out, args[0]);
             let b = get_num(out, args[1]);
-            out.add(Cad::Num((a / b).into()))
+            num_or(out, a / b, Cad::Div(args))
+        }
+        Cad::Pow(args) => {
+            let args = args.map(|arg| eval(cx, expr, arg, out));
+            let a = get_num(out, args[0]);
+            let b = get_num(out, args[1]);
+            num_or(out, a.powf(b), Cad::Pow(args))
+        }
+        Cad::Mod(args) => {
+            let args = args.map(|arg| eval(cx, expr, arg, out));
+            let a = get_num(out, args[0]);
+            let b = get_num(out, args[1]);
+            num_or(out, a % b, Cad::Mod(args))
+        }
+        Cad::Sin(args) => {
+            let arg = eval(cx, expr, args[0], out);
+            let a = get_num(out, arg);
+            out.add(Cad::Num(a.to_radians().sin().into()))
+        }
+        Cad::Cos(args) => {
+            let arg = eval(cx, expr, args[0], out);
+            let a = get_num(out, arg);
+            out.add(Cad::Num(a.to_radians().cos().into()))
+        }
+        Cad::Sqrt(args) => {
+            let arg = eval(cx, expr, args[0], out);
+            let a = get_num(out, arg);
+            num_or(out, a.sqrt(), Cad::Sqrt([arg]))
         }
+        Cad::Pi => out.add(Cad::Num(std::f64::consts::PI.into())),
         // cad
         Cad::Cube(args) => {
             let args = args.map(|arg| eval(cx, expr, arg, out));
@@ -102,28 +223,13 @@ fn eval_(cx: Option<&FunCtx>, expr: &RecExpr<Cad>, p: Id, out: &mut RecExpr<Cad>
             out.add(Cad::Hull(args))
         }
 
-        Cad::Trans | Cad::Scale | Cad::Rotate | Cad::TransPolar => out.add(e.clone()),
+        Cad::Trans | Cad::Scale | Cad::Rotate | Cad::TransPolar | Cad::Mirror => out.add(e.clone()),
 
         Cad::Affine(args) => {
             let aff = eval(cx, expr, args[0], out);
-            match out[aff] {
-                Cad::Trans | Cad::Scale | Cad::Rotate => {
-                    let param = eval(cx, expr, args[1], out);
-                    let cad = eval(cx, expr, args[2], out);
-                    out.add(Cad::Affine([aff, param, cad]))
-                }
-                Cad::TransPolar => {
-                    let param = eval(cx, expr, args[1], out);
-                    let cad = eval(cx, expr, args[2], out);
-                    let pnums = get_vec3_nums(out, param);
-                    let cnums = to_cartesian(pnums);
-
-                    let trans = out.add(Cad::Trans);
-                    let cnums = mk_vec(cnums, out);
-                    out.add(Cad::Affine([trans, cnums, cad]))
-                }
-                _ => panic!("expected affine kind, got {:?}", aff),
-            }
+            let param = eval(cx, expr, args[1], out);
+            let cad = eval(cx, expr, args[2], out);
+            affine(aff, param, cad, out)
         }
 
         Cad::Diff => out.add(Cad::Diff),
@@ -179,47 +285,175 @@ fn eval_(cx: Option<&FunCtx>, expr: &RecExpr<Cad>, p: Id, out: &mut RecExpr<Cad>
             );
             out.add(list)
         }
-        Cad::MapI(args) => {
-            let body = *args.last().unwrap();
-            let bounds: Vec<usize> = args[..args.len() - 1]
-                .iter()
-                .map(|n| get_num(expr, *n) as usize)
+        Cad::Iterate(args) => {
+            let aff = eval(cx, expr, args[1], out);
+            let param = eval(cx, expr, args[2], out);
+            let cads: Vec<_> = eval_list(cx, expr, args[3], out);
+            check_count(cx, expr, ("Iterate", args[0]), cads.len(), out);
+            let list = cads
+                .into_iter()
+                .enumerate()
+                .map(|(k, cad)| (0..k).fold(cad, |cad, _| affine(aff, param, cad, out)))
                 .collect();
+            out.add(mk_list(list))
+        }
+        Cad::MapI(args) => {
+            let (&body, bounds) = args.split_last().unwrap();
+            assert!(bounds.len() <= 3, "MapI with {} bounds", bounds.len());
             let mut ctx = HashMap::new();
             let mut vec = Vec::new();
-            match bounds.len() {
-                1 => {
-                    for i in 0..bounds[0] {
-                        ctx.insert("i", i);
-                        vec.push(eval(Some(&ctx), expr, body, out));
-                    }
-                }
-                2 => {
-                    for i in 0..bounds[0] {
-                        ctx.insert("i", i);
-                        for j in 0..bounds[1] {
-                            ctx.insert("j", j);
-                            vec.push(eval(Some(&ctx), expr, body, out));
-                        }
-                    }
-                }
-                3 => {
-                    for i in 0..bounds[0] {
-                        ctx.insert("i", i);
-                        for j in 0..bounds[1] {
-                            ctx.insert("j", j);
-                            for k in 0..bounds[2] {
-                                ctx.insert("k", k);
-                                vec.push(eval(Some(&ctx), expr, body, out));
-                            }
-                        }
-                    }
+            let vars = ["i", "j", "k"];
+            tabulate(&mut ctx, &vars, bounds, expr, body, out, &mut vec);
+            out.add(mk_list(vec))
+        }
+        // structure edits
+        Cad::Sort(args) => {
+            let list = eval_list(cx, expr, args[1], out);
+            match &expr[args[0]] {
+                Cad::Permutation(perm) => out.add(mk_list(perm.apply(&list))),
+                cad => panic!("expected permutation, got {:?}", cad),
+            }
+        }
+        Cad::Unsort(args) => {
+            let list = eval_list(cx, expr, args[1], out);
+            match &expr[args[0]] {
+                Cad::Permutation(perm) => out.add(mk_list(perm.invert().apply(&list))),
+                cad => panic!("expected permutation, got {:?}", cad),
+            }
+        }
+        Cad::Remove(args) => {
+            let list = eval_list(cx, expr, args[0], out);
+            let indices: Vec<usize> = eval_list(cx, expr, args[1], out)
+                .into_iter()
+                .map(|i| {
+                    let i = get_num(out, i);
+                    let whole = i.round();
+                    assert!(
+                        whole >= 0.0 && Num::from(i).is_close(whole),
+                        "Remove of index {}",
+                        i
+                    );
+                    whole as usize
+                })
+                .collect();
+            let kept = list
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| !indices.contains(i))
+                .map(|(_, id)| id)
+                .collect();
+            out.add(mk_list(kept))
+        }
+        Cad::Part(args) => {
+            let list = eval_list(cx, expr, args[1], out);
+            match &expr[args[0]] {
+                Cad::Partitioning(part) => {
+                    let lists = part
+                        .apply(&list)
+                        .into_iter()
+                        .map(|sublist| out.add(mk_list(sublist)))
+                        .collect();
+                    out.add(mk_list(lists))
                 }
-                _ => unimplemented!(),
+                cad => panic!("expected partitioning, got {:?}", cad),
+            }
+        }
+        Cad::Unpart(args) => {
+            let mut vec = Vec::new();
+            for list in eval_list(cx, expr, args[1], out) {
+                vec.extend(get_list(out, list).iter().copied())
             }
-
             out.add(mk_list(vec))
         }
+        Cad::Unpolar(args) => {
+            let center = eval(cx, expr, args[1], out);
+            let center = get_vec3_nums(out, center);
+            let params = eval_list(cx, expr, args[2], out);
+            check_count(cx, expr, ("Unpolar", args[0]), params.len(), out);
+            let list = params
+                .into_iter()
+                .map(|p| {
+                    let v = to_cartesian(get_vec3_nums(out, p));
+                    mk_vec((center.0 + v.0, center.1 + v.1, center.2 + v.2), out)
+                })
+                .collect();
+            out.add(mk_list(list))
+        }
         cad => panic!("can't eval({:?})", cad),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_value_stays_unevaluated() {
+        for input in ["(pow -8 0.5)", "(% 3 0)", "(/ 1 0)", "(sqrt -4)"] {
+            let expr: RecExpr<Cad> = input.parse().unwrap();
+            let mut out = RecExpr::from(vec![]);
+            let root = eval(None, &expr, (expr.as_ref().len() - 1).into(), &mut out);
+            assert!(
+                matches!(
+                    out[root],
+                    Cad::Pow(_) | Cad::Mod(_) | Cad::Div(_) | Cad::Sqrt(_)
+                ),
+                "{}",
+                out
+            );
+        }
+    }
+
+    fn eval_str(input: &str) -> RecExpr<Cad> {
+        let expr: RecExpr<Cad> = input.parse().unwrap();
+        let mut out = RecExpr::from(vec![]);
+        eval(None, &expr, (expr.as_ref().len() - 1).into(), &mut out);
+        out
+    }
+
+    #[test]
+    fn counts_match_lists() {
+        eval_str("(Iterate 3 Scale (Vec3 2 2 2) (Repeat 3 (Cube (Vec3 1 1 1) false)))");
+        eval_str("(Unpolar 2 (Vec3 0 0 0) (List (Vec3 1 0 90) (Vec3 1 90 90)))");
+    }
+
+    #[test]
+    fn affine_moves_positions() {
+        let out = eval_str("(Affine Rotate (Vec3 90 0 0) (List (Vec3 0 1 0) (Vec3 2 0 0)))");
+        let points: Vec<_> = get_list(&out, (out.as_ref().len() - 1).into())
+            .iter()
+            .map(|&p| get_vec3_nums(&out, p))
+            .collect();
+        let close = |p: (f64, f64, f64), q: (f64, f64, f64)| {
+            [p.0 - q.0, p.1 - q.1, p.2 - q.2]
+                .iter()
+                .all(|&d| Num::from(d).is_close(0))
+        };
+        assert!(close(points[0], (0.0, 0.0, 1.0)));
+        assert!(close(points[1], (2.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Remove of index -1")]
+    fn remove_negative_index() {
+        eval_str("(Remove (Repeat 3 (Cube (Vec3 1 1 1) false)) (List -1))");
+    }
+
+    #[test]
+    #[should_panic(expected = "Remove of index 0.5")]
+    fn remove_fractional_index() {
+        eval_str("(Remove (Repeat 3 (Cube (Vec3 1 1 1) false)) (List 0.5))");
+    }
+
+    #[test]
+    #[should_panic(expected = "Iterate of 5 over a list of 3")]
+    fn iterate_count_mismatch() {
+        eval_str("(Iterate 5 Scale (Vec3 2 2 2) (Repeat 3 (Cube (Vec3 1 1 1) false)))");
+    }
+
+    #[test]
+    #[should_panic(expected = "Unpolar of 3 over a list of 2")]
+    fn unpolar_count_mismatch() {
+        eval_str("(Unpolar 3 (Vec3 0 0 0) (List (Vec3 1 0 90) (Vec3 1 90 90)))");
+    }
+}
@@ -1 +1,2 @@
+pub mod raster;
 pub mod scad;
@@ -0,0 +1,309 @@
+/// Raster
+/// A CPU-only renderer for previews of CAD programs, without OpenSCAD.
+/// It renders a z-slice or a top-down orthographic projection into an RGB image,
+/// which can be written as PPM or PNG.
+use egg::RecExpr;
+
+use crate::base::geom::BBox;
+use crate::cad::Cad;
+use crate::hyperparameters::{RASTER_DEPTH_STEPS, RASTER_MARGIN};
+use crate::solid::Solid;
+
+pub type Rgb = [u8; 3];
+
+const BACKGROUND: Rgb = [255, 255, 255];
+const INSIDE: Rgb = [40, 40, 40];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum View {
+    /// The cross-section at the given height.
+    Slice(f64),
+    /// Looking down the z axis; higher surfaces are lighter.
+    Projection,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Rgb>,
+}
+
+/// The region of space mapped onto the image. Share one `Raster` between
+/// renders to get images that line up, e.g. before and after optimisation.
+#[derive(Debug, Clone, Copy)]
+pub struct Raster {
+    pub frame: BBox,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Raster {
+    /// Frame the whole program, keeping its aspect ratio.
+    pub fn fit(expr: &RecExpr<Cad>, width: usize) -> Option<Raster> {
+        let b = Solid::new(expr)?.bbox();
+        if b.is_empty() || width == 0 {
+            return None;
+        }
+        let pad = RASTER_MARGIN * (b.max.0 - b.min.0).max(b.max.1 - b.min.1).max(1e-9);
+        let frame = BBox::new(
+            (b.min.0 - pad, b.min.1 - pad, b.min.2),
+            (b.max.0 + pad, b.max.1 + pad, b.max.2),
+        );
+        let aspect = (frame.max.1 - frame.min.1) / (frame.max.0 - frame.min.0);
+        let height = ((width as f64 * aspect).round() as usize).max(1);
+        Some(Raster {
+            frame,
+            width,
+            height,
+        })
+    }
+
+    /// `None` if the program has no solid or the image no pixels.
+    pub fn render(&self, expr: &RecExpr<Cad>, view: View) -> Option<Image> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        let solid = Solid::new(expr)?;
+        let f = &self.frame;
+        let dx = (f.max.0 - f.min.0) / self.width as f64;
+        let dy = (f.max.1 - f.min.1) / self.height as f64;
+        let mut pixels = Vec::with_capacity(self.width * self.height);
+        // image rows go top to bottom, so y decreases
+        for row in 0..self.height {
+            let y = f.max.1 - (row as f64 + 0.5) * dy;
+            for col in 0..self.width {
+                let x = f.min.0 + (col as f64 + 0.5) * dx;
+                let pixel = match view {
+                    View::Slice(z) => {
+                        if solid.contains((x, y, z)) {
+                            INSIDE
+                        } else {
+                            BACKGROUND
+                        }
+                    }
+                    View::Projection => self.shade_column(&solid, x, y),
+                };
+                pixels.push(pixel);
+            }
+        }
+        Some(Image {
+            width: self.width,
+            height: self.height,
+            pixels,
+        })
+    }
+
+    // march down from the top of the frame and shade by the height of the first hit
+    fn shade_column(&self, solid: &Solid, x: f64, y: f64) -> Rgb {
+        let (z0, z1) = (self.frame.min.2, self.frame.max.2);
+        let steps = RASTER_DEPTH_STEPS;
+        for step in 0..=steps {
+            let t = step as f64 / steps as f64;
+            let z = z1 - t * (z1 - z0);
+            if solid.contains((x, y, z)) {
+                let shade = 200.0 - 160.0 * t;
+                return [shade as u8, shade as u8, (shade + 40.0) as u8];
+            }
+        }
+        BACKGROUND
+    }
+}
+
+impl Image {
+    /// Binary PPM (P6).
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut out = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        out.extend(self.pixels.iter().flatten());
+        out
+    }
+
+    /// An 8-bit RGB PNG. The image data is stored uncompressed. PNG has no images without
+    /// pixels, so decoders reject the output for a width or height of 0.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity((3 * self.width + 1) * self.height);
+        for row in 0..self.height {
+            // filter type: none
+            raw.push(0);
+            let row = &self.pixels[row * self.width..(row + 1) * self.width];
+            raw.extend(row.iter().flatten());
+        }
+
+        let mut ihdr = Vec::new();
+        ihdr.extend((self.width as u32).to_be_bytes());
+        ihdr.extend((self.height as u32).to_be_bytes());
+        // bit depth 8, color type RGB, default compression, filter and interlace
+        ihdr.extend([8, 2, 0, 0, 0]);
+
+        let mut out = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+        png_chunk(&mut out, b"IHDR", &ihdr);
+        png_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+        png_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+// a zlib stream made of stored (uncompressed) deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 0xffff;
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        out.push(last as u8);
+        let len = block.len() as u16;
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // checks the chunks and the zlib stream, and returns the size and pixels
+    fn decode_png(png: &[u8]) -> (usize, usize, Vec<Rgb>) {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let be = |b: &[u8]| u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+        let (mut size, mut idat, mut rest) = (None, vec![], &png[8..]);
+        while !rest.is_empty() {
+            let len = be(rest) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+            assert_eq!(crc32(&rest[4..8 + len]), be(&rest[8 + len..]));
+            match kind {
+                b"IHDR" => {
+                    assert_eq!(&data[8..], [8, 2, 0, 0, 0]);
+                    size = Some((be(data) as usize, be(&data[4..]) as usize));
+                }
+                b"IDAT" => idat.extend_from_slice(data),
+                b"IEND" => assert!(data.is_empty()),
+                _ => panic!("unexpected chunk {:?}", kind),
+            }
+            rest = &rest[12 + len..];
+        }
+
+        assert_eq!((u16::from(idat[0]) << 8 | u16::from(idat[1])) % 31, 0);
+        let (mut raw, mut at) = (vec![], 2);
+        loop {
+            let last = idat[at] & 1 == 1;
+            assert_eq!(idat[at] >> 1, 0, "only stored blocks");
+            let len = u16::from_le_bytes([idat[at + 1], idat[at + 2]]);
+            let nlen = u16::from_le_bytes([idat[at + 3], idat[at + 4]]);
+            assert_eq!(len, !nlen);
+            raw.extend_from_slice(&idat[at + 5..at + 5 + len as usize]);
+            at += 5 + len as usize;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(adler32(&raw), be(&idat[at..]));
+        assert_eq!(at + 4, idat.len());
+
+        let (width, height) = size.unwrap();
+        let pixels = raw
+            .chunks(3 * width + 1)
+            .flat_map(|row| {
+                assert_eq!(row[0], 0);
+                row[1..].chunks(3).map(|p| [p[0], p[1], p[2]])
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(pixels.len(), width * height);
+        (width, height, pixels)
+    }
+
+    fn image(width: usize, height: usize) -> Image {
+        let pixels = (0..width * height)
+            .map(|i| [i as u8, (i / 256) as u8, 7])
+            .collect();
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    #[test]
+    fn png_round_trip() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        // the second one needs more than one stored block
+        for (width, height) in [(3, 2), (200, 120)] {
+            let img = image(width, height);
+            assert_eq!(decode_png(&img.to_png()), (width, height, img.pixels));
+        }
+    }
+
+    #[test]
+    fn ppm_layout() {
+        let img = image(3, 2);
+        let ppm = img.to_ppm();
+        let header = b"P6\n3 2\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        assert_eq!(ppm.len(), header.len() + 3 * 6);
+        assert_eq!(ppm[header.len() + 3..header.len() + 6], img.pixels[1]);
+    }
+
+    #[test]
+    fn slice() {
+        let expr: RecExpr<Cad> = "(Cube (Vec3 2 4 2) false)".parse().unwrap();
+        let raster = Raster {
+            frame: BBox::new((0.0, 0.0, 0.0), (4.0, 4.0, 2.0)),
+            width: 4,
+            height: 4,
+        };
+        let img = raster.render(&expr, View::Slice(1.0)).unwrap();
+        for row in img.pixels.chunks(4) {
+            assert_eq!(row, [INSIDE, INSIDE, BACKGROUND, BACKGROUND]);
+        }
+        let above = raster.render(&expr, View::Slice(3.0)).unwrap();
+        assert!(above.pixels.iter().all(|&p| p == BACKGROUND));
+    }
+
+    #[test]
+    fn no_pixels() {
+        let expr: RecExpr<Cad> = "(Cube (Vec3 2 4 2) false)".parse().unwrap();
+        assert!(Raster::fit(&expr, 0).is_none());
+        let raster = Raster {
+            width: 0,
+            ..Raster::fit(&expr, 8).unwrap()
+        };
+        assert!(raster.render(&expr, View::Projection).is_none());
+        // does not panic
+        image(0, 2).to_png();
+    }
+}
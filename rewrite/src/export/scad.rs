@@ -7,7 +7,9 @@ use crate::cad_struct::get_vec3_nums;
 
 use crate::cad::Cad;
 
+use crate::base::num::{num, Num};
 use crate::eval::eval;
+use crate::hyperparameters::SCAD_DIGITS;
 
 pub struct Scad<'a>(pub &'a RecExpr<Cad>, pub Id);
 
@@ -31,6 +33,12 @@ impl<'a> fmt::Display for Scad<'a> {
                 Cad::Sub(children) => write!(f, "{} - {}", children[0], children[1]),
                 Cad::Mul(children) => write!(f, "{} * {}", children[0], children[1]),
                 Cad::Div(children) => write!(f, "{} / {}", children[0], children[1]),
+                Cad::Pow(children) => write!(f, "pow({}, {})", children[0], children[1]),
+                Cad::Mod(children) => write!(f, "{} % {}", children[0], children[1]),
+                Cad::Sin(children) => write!(f, "sin({})", children[0]),
+                Cad::Cos(children) => write!(f, "cos({})", children[0]),
+                Cad::Sqrt(children) => write!(f, "sqrt({})", children[0]),
+                Cad::Pi => write!(f, "PI"),
                 Cad::Empty => writeln!(f, "sphere(r=0);"),
                 Cad::Cube(_) => writeln!(f, "cube({}, center={});", child(0), child(1)),
                 Cad::Sphere(_) => writeln!(
@@ -63,6 +71,7 @@ impl<'a> fmt::Display for Scad<'a> {
                 Cad::Trans => write!(f, "translate"),
                 Cad::Scale => write!(f, "scale"),
                 Cad::Rotate => write!(f, "rotate"),
+                Cad::Mirror => write!(f, "mirror"),
                 Cad::Affine(_) => write!(f, "{} ({}) {}", child(0), child(1), child(2)),
 
                 Cad::Union => write!(f, "union"),
@@ -85,9 +94,333 @@ impl<'a> fmt::Display for Scad<'a> {
                 cad => panic!("TODO: {:?}", cad),
             }
         };
+        // keep loops with bounds that OpenSCAD can express, instead of unrolling them
+        if let Some(loops) = nested_loops(self.0, self.1) {
+            return write!(f, "{}", loops);
+        }
         // may need to shrink expr to match self.1
         let mut normalform = RecExpr::from(vec![]);
         let p = eval(None, self.0, self.1, &mut normalform);
         fmt_impl(p, &normalform)
     }
 }
+
+// Loops below affines and booleans are kept too. The parts of the term without loops are
+// written as before.
+fn nested_loops(expr: &RecExpr<Cad>, p: Id) -> Option<String> {
+    let boolean = |op: Id| match expr[op] {
+        Cad::Union => Some("union"),
+        Cad::Inter => Some("intersection"),
+        Cad::Diff => Some("difference"),
+        _ => None,
+    };
+    let block = |op: &str, cads: &[Id]| {
+        if !cads.iter().any(|&cad| has_loop(expr, cad)) {
+            return None;
+        }
+        let mut out = format!("{} () {{\n", op);
+        for &cad in cads {
+            out += &format!("  {}", Scad(expr, cad));
+        }
+        Some(out + "}")
+    };
+    match &expr[p] {
+        Cad::Affine([op, param, cad]) => {
+            let op = affine_name(&expr[*op])?;
+            let [x, y, z] = vec3(expr, *param)?;
+            let cad = nested_loops(expr, *cad)?;
+            Some(format!("{} ([{}, {}, {}]) {}", op, x, y, z, cad))
+        }
+        Cad::Binop([op, a, b]) => block(boolean(*op)?, &[*a, *b]),
+        Cad::Fold([op, list]) => match &expr[*list] {
+            Cad::List(cads) => block(boolean(*op)?, cads),
+            _ if expr[*op] == Cad::Union => loops(expr, "union", *list),
+            _ => None,
+        },
+        Cad::Hull([list]) => loops(expr, "hull", *list),
+        _ => None,
+    }
+}
+
+fn has_loop(expr: &RecExpr<Cad>, p: Id) -> bool {
+    matches!(expr[p], Cad::MapI(_) | Cad::Iterate(_))
+        || expr[p].children().iter().any(|&c| has_loop(expr, c))
+}
+
+fn affine_name(op: &Cad) -> Option<&'static str> {
+    match op {
+        Cad::Trans => Some("translate"),
+        Cad::Scale => Some("scale"),
+        Cad::Rotate => Some("rotate"),
+        Cad::Mirror => Some("mirror"),
+        _ => None,
+    }
+}
+
+// The i-th power of an iterated affine, when OpenSCAD can say it without recursion.
+// Turns about more than one axis do not add up, and are left unrolled.
+fn iterated(expr: &RecExpr<Cad>, op: Id, param: Id) -> Option<String> {
+    let v = vec3(expr, param)?;
+    let times = |a: &String| match a.as_str() {
+        "0" => a.clone(),
+        _ => format!("{} * i", a),
+    };
+    match expr[op] {
+        Cad::Trans => Some(format!(
+            "translate ([{}, {}, {}]) ",
+            times(&v[0]),
+            times(&v[1]),
+            times(&v[2])
+        )),
+        Cad::Scale => Some(format!(
+            "scale ([pow({}, i), pow({}, i), pow({}, i)]) ",
+            v[0], v[1], v[2]
+        )),
+        Cad::Rotate if v.iter().filter(|a| a.as_str() != "0").count() <= 1 => Some(format!(
+            "rotate ([{}, {}, {}]) ",
+            times(&v[0]),
+            times(&v[1]),
+            times(&v[2])
+        )),
+        _ => None,
+    }
+}
+
+// (MapI n1 .. body) under a union or hull as nested for loops, if the body only uses
+// arithmetic, affines and primitives. Affines of the whole list are written before the
+// loops, and iterates of a single loop as powers of their affines in the body.
+fn loops(expr: &RecExpr<Cad>, op: &str, list: Id) -> Option<String> {
+    let mut out = format!("{} () {{\n", op);
+    let mut list = list;
+    while let Cad::Affine([aff, param, inner]) = &expr[list] {
+        let [x, y, z] = vec3(expr, *param)?;
+        let aff = affine_name(&expr[*aff])?;
+        out += &format!("  {} ([{}, {}, {}])\n", aff, x, y, z);
+        list = *inner;
+    }
+    let mut powers = String::new();
+    while let Cad::Iterate([_, aff, param, inner]) = &expr[list] {
+        powers += &iterated(expr, *aff, *param)?;
+        list = *inner;
+    }
+
+    let (list, removed) = match &expr[list] {
+        Cad::Remove([list, indices]) => (&expr[*list], Some(&expr[*indices])),
+        list => (list, None),
+    };
+    let (body, bounds) = match list {
+        Cad::MapI(args) => args.split_last().unwrap(),
+        Cad::Repeat([n, cad]) => (cad, std::slice::from_ref(n)),
+        _ => return None,
+    };
+    // the power of an iterate is the index of the element in the whole list
+    let flat = bounds.len() == 1 && !matches!(expr[bounds[0]], Cad::Range(_)) && removed.is_none();
+    if !powers.is_empty() && !flat {
+        return None;
+    }
+    for (var, &bound) in ["i", "j", "k"].iter().zip(bounds) {
+        let range = match &expr[bound] {
+            Cad::Range([start, step, n]) => match (&expr[*start], &expr[*step], &expr[*n]) {
+                (Cad::Num(start), Cad::Num(step), Cad::Num(n)) => {
+                    // rounded, so that 0.1 + 0.1 * 2 is written as 0.3
+                    let end = start.to_f64() + step.to_f64() * (n.to_f64() - 1.0);
+                    let scale = 10f64.powi(SCAD_DIGITS);
+                    let end = num((end * scale).round() / scale + 0.0);
+                    format!("{} : {} : {}", start, step, end)
+                }
+                _ => {
+                    let (start, step) = (arith(expr, *start)?, arith(expr, *step)?);
+                    let end = format!("{} + {} * ({} - 1)", start, step, arith(expr, *n)?);
+                    format!("{} : {} : {}", start, step, end)
+                }
+            },
+            _ => format!("0 : 1 : {} - 1", arith(expr, bound)?),
+        };
+        out += &format!("  for ({} = [{}])\n", var, range);
+    }
+    if let Some(removed) = removed {
+        out += &format!("  if (!({}))\n", skipped(expr, bounds, removed)?);
+    }
+    out += &format!("    {}{}}}", powers, loop_body(expr, *body)?);
+    Some(out)
+}
+
+// The condition on the loop variables that picks out the removed elements. Only for
+// constant bounds counting from 0, so that a flat index splits into one index per loop.
+fn skipped(expr: &RecExpr<Cad>, bounds: &[Id], removed: &Cad) -> Option<String> {
+    let lens = bounds
+        .iter()
+        .map(|&n| match &expr[n] {
+            Cad::Num(n) => whole(n).filter(|&len| len > 0),
+            _ => None,
+        })
+        .collect::<Option<Vec<usize>>>()?;
+    let total: usize = lens.iter().product();
+    let indices = match removed {
+        Cad::List(indices) => indices,
+        _ => return None,
+    };
+    let mut conds = vec![];
+    for &index in indices {
+        let mut flat = match &expr[index] {
+            Cad::Num(i) => whole(i).filter(|&i| i < total)?,
+            _ => return None,
+        };
+        let mut vars = vec![];
+        for (var, len) in ["i", "j", "k"].iter().zip(&lens).rev() {
+            vars.push(format!("{} == {}", var, flat % len));
+            flat /= len;
+        }
+        vars.reverse();
+        conds.push(format!("({})", vars.join(" && ")));
+    }
+    Some(conds.join(" || "))
+}
+
+// A count or an index, if the number is a whole one and not negative.
+fn whole(n: &Num) -> Option<usize> {
+    let rounded = n.to_f64().round();
+    (rounded >= 0.0 && n.is_close(rounded)).then_some(rounded as usize)
+}
+
+fn arith(expr: &RecExpr<Cad>, p: Id) -> Option<String> {
+    let bin = |op: &str, args: &[Id; 2]| {
+        Some(format!(
+            "({} {} {})",
+            arith(expr, args[0])?,
+            op,
+            arith(expr, args[1])?
+        ))
+    };
+    match &expr[p] {
+        Cad::Num(n) => Some(format!("{}", n)),
+        Cad::ListVar(v) => Some(v.0.to_string()),
+        Cad::Add(args) => bin("+", args),
+        Cad::Sub(args) => bin("-", args),
+        Cad::Mul(args) => bin("*", args),
+        Cad::Div(args) => bin("/", args),
+        Cad::Mod(args) => bin("%", args),
+        Cad::Pow(args) => Some(format!(
+            "pow({}, {})",
+            arith(expr, args[0])?,
+            arith(expr, args[1])?
+        )),
+        Cad::Sin(args) => Some(format!("sin({})", arith(expr, args[0])?)),
+        Cad::Cos(args) => Some(format!("cos({})", arith(expr, args[0])?)),
+        Cad::Sqrt(args) => Some(format!("sqrt({})", arith(expr, args[0])?)),
+        Cad::Pi => Some("PI".to_string()),
+        _ => None,
+    }
+}
+
+fn vec3(expr: &RecExpr<Cad>, p: Id) -> Option<[String; 3]> {
+    match &expr[p] {
+        Cad::Vec3(args) => Some([
+            arith(expr, args[0])?,
+            arith(expr, args[1])?,
+            arith(expr, args[2])?,
+        ]),
+        _ => None,
+    }
+}
+
+fn loop_body(expr: &RecExpr<Cad>, p: Id) -> Option<String> {
+    let boolean = |id: Id| match expr[id] {
+        Cad::Bool(b) => Some(b),
+        _ => None,
+    };
+    match &expr[p] {
+        Cad::Empty => Some("sphere(r=0);\n".to_string()),
+        Cad::Cube(args) => {
+            let [x, y, z] = vec3(expr, args[0])?;
+            Some(format!(
+                "cube([{}, {}, {}], center={});\n",
+                x,
+                y,
+                z,
+                boolean(args[1])?
+            ))
+        }
+        Cad::Sphere(args) => {
+            let [fn_, fa, fs] = vec3(expr, args[1])?;
+            Some(format!(
+                "sphere(r = {}, $fn = {}, $fa = {}, $fs = {});\n",
+                arith(expr, args[0])?,
+                fn_,
+                fa,
+                fs
+            ))
+        }
+        Cad::Cylinder(args) => {
+            let [h, r1, r2] = vec3(expr, args[0])?;
+            let [fn_, fa, fs] = vec3(expr, args[1])?;
+            Some(format!(
+                "cylinder(h = {}, r1 = {}, r2 = {}, $fn = {}, $fa = {}, $fs = {}, center = {});\n",
+                h,
+                r1,
+                r2,
+                fn_,
+                fa,
+                fs,
+                boolean(args[2])?
+            ))
+        }
+        Cad::Affine(args) => {
+            let op = affine_name(&expr[args[0]])?;
+            let [x, y, z] = vec3(expr, args[1])?;
+            Some(format!(
+                "{} ([{}, {}, {}]) {}",
+                op,
+                x,
+                y,
+                z,
+                loop_body(expr, args[2])?
+            ))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_end_is_rounded() {
+        let expr: RecExpr<Cad> = "(Fold Union (MapI (Range 0.1 0.1 3) (Cube (Vec3 i 1 1) false)))"
+            .parse()
+            .unwrap();
+        let scad = Scad::new(&expr).to_string();
+        assert!(scad.contains("for (i = [0.1 : 0.1 : 0.3])"), "{}", scad);
+    }
+
+    fn skip(bounds: &str, indices: &str) -> Option<String> {
+        let expr: RecExpr<Cad> = format!("(Remove (MapI {} 0) (List {}))", bounds, indices)
+            .parse()
+            .unwrap();
+        match expr.as_ref().last() {
+            Some(Cad::Remove([mapi, removed])) => match &expr[*mapi] {
+                Cad::MapI(args) => skipped(&expr, &args[..args.len() - 1], &expr[*removed]),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn skipped_indices() {
+        let cond = skip("2 3", "1 5");
+        assert_eq!(
+            cond.as_deref(),
+            Some("(i == 0 && j == 1) || (i == 1 && j == 2)")
+        );
+
+        // no loop to split an index over, or no element it stands for
+        assert_eq!(skip("0", "0"), None);
+        assert_eq!(skip("2.5", "1"), None);
+        assert_eq!(skip("-2", "1"), None);
+        assert_eq!(skip("2 3", "6"), None);
+        assert_eq!(skip("2 3", "-1"), None);
+        assert_eq!(skip("2 3", "1.5"), None);
+    }
+}
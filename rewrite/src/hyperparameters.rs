@@ -2,17 +2,58 @@
 pub const COST_BIG_VALUE: f64 = 100_000_000.0;
 pub const COST_SMALL_VALUE: f64 = 0.001;
 
+// cost.rs
+pub const NUM_FREE_DIGITS: usize = 4;
+pub const NUM_DIGIT_COST: f64 = 0.3;
+
 // num.rs
 pub const ABS_EPSILON: f64 = 0.0001;
 pub const REL_EPSILON: f64 = 0.0001;
 
 // rules.rs
 pub const CAD_IDENTS: bool = true;
+pub const BOOL_ALGEBRA: bool = true;
 pub const INV_TRANS: bool = true;
 pub const PARTITIONING: bool = true;
 pub const PARTITIONING_MAX: usize = 5;
 pub const AFFINE_SIGNATURE_MAX_LEN: usize = 10;
 pub const STRUCTURE_MATCH_LIMIT: usize = 1000;
+pub const ANTI_UNIFY_MAX_HOLES: usize = 6;
+pub const ROTATE_DIGITS: i32 = 6;
+pub const COALESCE_MAX: usize = 200;
 
 // solve.rs
 pub const SOLVE_ROUND: f64 = 0.01;
+pub const SOLVE_TOLERANCE: f64 = ABS_EPSILON;
+pub const SOLVE_MAX_DEGREE: usize = 4;
+pub const POLAR_LEAVE_ONE_OUT_MAX: usize = 12;
+pub const REMOVE_MAX: usize = 8;
+
+// snap.rs; exact numbers are not noise
+pub const SNAP_NUMS: bool = !cfg!(feature = "exact");
+
+// constant.rs; nor a float's closed form
+pub const CONSTANTS: bool = !cfg!(feature = "exact");
+pub const CONST_MAX_DENOM: i64 = 12;
+pub const CONST_MAX_NUMER: i64 = 1000;
+pub const CONST_MAX_PARTS: i64 = 64;
+pub const CONST_TOLERANCE: f64 = 0.000001;
+
+// measure.rs
+pub const MEASURE_SEED: u64 = 0x5eed;
+pub const MEASURE_SAMPLES: usize = 100_000;
+pub const MEASURE_SHELL: f64 = 0.01;
+
+// scad.rs
+pub const SCAD_DIGITS: i32 = 6;
+
+// raster.rs
+pub const RASTER_WIDTH: usize = 256;
+pub const RASTER_MARGIN: f64 = 0.05;
+pub const RASTER_DEPTH_STEPS: usize = 64;
+
+// check.rs
+pub const CHECK_SEED: u64 = 0xc4ec;
+pub const CHECK_INSTANCES: usize = 20;
+pub const CHECK_SAMPLES: usize = 2000;
+pub const CHECK_TOLERANCE: f64 = 0.005;
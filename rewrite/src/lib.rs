@@ -50,7 +50,22 @@ pub mod prune;
 pub mod rules;
 
 pub mod eval;
-mod solve;
+pub mod solve;
+
+// Merge nearly equal numbers
+pub mod snap;
+
+// Closed forms of long numbers
+pub mod constant;
+
+// Point-membership view of a CAD program
+pub mod solid;
+
+// Volume and surface area
+pub mod measure;
+
+// Soundness checks for rewrites
+pub mod check;
 
 // Export
 pub mod export;
@@ -0,0 +1,168 @@
+/// Measure
+/// Volume and surface area of CAD programs. A single primitive under affines is measured exactly,
+/// everything else is estimated by seeded Monte Carlo sampling over the bounding box, so the same
+/// geometry always gets the same estimate.
+use std::f64::consts::PI;
+
+use egg::RecExpr;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64;
+
+use crate::base::geom::{Affine3, BBox, Point};
+use crate::cad::Cad;
+use crate::hyperparameters::{MEASURE_SAMPLES, MEASURE_SEED, MEASURE_SHELL};
+use crate::solid::{Prim, Solid};
+
+pub fn volume(expr: &RecExpr<Cad>) -> Option<f64> {
+    let solid = Solid::new(expr)?;
+    let v = match &solid {
+        Solid::Empty => 0.0,
+        Solid::Prim { prim, to_world, .. } => prim_volume(prim) * to_world.det().abs(),
+        _ => sampled_volume(&solid),
+    };
+    Some(v)
+}
+
+pub fn area(expr: &RecExpr<Cad>) -> Option<f64> {
+    let solid = Solid::new(expr)?;
+    let a = match &solid {
+        Solid::Empty => 0.0,
+        Solid::Prim {
+            prim,
+            to_world,
+            to_local,
+        } => prim_area(prim, to_world, to_local).unwrap_or_else(|| sampled_area(&solid)),
+        _ => sampled_area(&solid),
+    };
+    Some(a)
+}
+
+fn prim_volume(prim: &Prim) -> f64 {
+    match *prim {
+        Prim::Cube(s) => (s.0 * s.1 * s.2).abs(),
+        Prim::Sphere(r) => 4.0 / 3.0 * PI * (r * r * r).abs(),
+        Prim::Cylinder { h, r1, r2 } => PI * h.abs() * (r1 * r1 + r1 * r2 + r2 * r2) / 3.0,
+    }
+}
+
+// the Gram matrix of the linear part, which tells how lengths are distorted
+fn gram(aff: &Affine3) -> [[f64; 3]; 3] {
+    let m = &aff.lin;
+    let mut g = [[0.0; 3]; 3];
+    for (i, row) in g.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| m[k][i] * m[k][j]).sum();
+        }
+    }
+    g
+}
+
+fn is_tiny(v: f64, scale: f64) -> bool {
+    v.abs() <= 1e-9 * scale.abs().max(1.0)
+}
+
+// Exact area of a transformed primitive, where one exists.
+// Curved primitives need the map to keep their cross-sections circular.
+fn prim_area(prim: &Prim, to_world: &Affine3, to_local: &Affine3) -> Option<f64> {
+    let g = gram(to_world);
+    match *prim {
+        Prim::Cube(s) => {
+            // a face with normal n and area a maps to area |det| |M^-T n| a
+            let det = to_world.det().abs();
+            let faces = [s.1 * s.2, s.0 * s.2, s.0 * s.1];
+            let area = faces
+                .iter()
+                .enumerate()
+                .map(|(k, face)| {
+                    let row = to_local.lin[k];
+                    let n = (row[0] * row[0] + row[1] * row[1] + row[2] * row[2]).sqrt();
+                    2.0 * det * n * face.abs()
+                })
+                .sum();
+            Some(area)
+        }
+        Prim::Sphere(r) => {
+            let s2 = (g[0][0] + g[1][1] + g[2][2]) / 3.0;
+            let similar = (0..3).all(|i| {
+                (0..3).all(|j| {
+                    let expected = if i == j { s2 } else { 0.0 };
+                    is_tiny(g[i][j] - expected, s2)
+                })
+            });
+            similar.then_some(4.0 * PI * r * r * s2)
+        }
+        Prim::Cylinder { h, r1, r2 } => {
+            let s2 = g[0][0];
+            let circular = is_tiny(g[1][1] - s2, s2)
+                && is_tiny(g[0][1], s2)
+                && is_tiny(g[0][2], s2)
+                && is_tiny(g[1][2], s2);
+            circular.then(|| {
+                let s = s2.sqrt();
+                let (r1, r2, h) = (s * r1.abs(), s * r2.abs(), g[2][2].sqrt() * h.abs());
+                let lateral = PI * (r1 + r2) * ((r1 - r2) * (r1 - r2) + h * h).sqrt();
+                lateral + PI * (r1 * r1 + r2 * r2)
+            })
+        }
+    }
+}
+
+fn box_volume(b: &BBox) -> f64 {
+    if b.is_empty() {
+        return 0.0;
+    }
+    (b.max.0 - b.min.0) * (b.max.1 - b.min.1) * (b.max.2 - b.min.2)
+}
+
+fn sample_point(rng: &mut Pcg64, b: &BBox) -> Point {
+    let mut along = |lo: f64, hi: f64| lo + (hi - lo) * rng.gen::<f64>();
+    (
+        along(b.min.0, b.max.0),
+        along(b.min.1, b.max.1),
+        along(b.min.2, b.max.2),
+    )
+}
+
+fn sampled_volume(solid: &Solid) -> f64 {
+    let b = solid.bbox();
+    let total = box_volume(&b);
+    if total == 0.0 {
+        return 0.0;
+    }
+    let mut rng = Pcg64::seed_from_u64(MEASURE_SEED);
+    let hits = (0..MEASURE_SAMPLES)
+        .filter(|_| solid.contains(sample_point(&mut rng, &b)))
+        .count();
+    total * hits as f64 / MEASURE_SAMPLES as f64
+}
+
+// Throw short needles of length eps with uniform direction into the padded bounding box.
+// A needle crosses the boundary with probability area * eps / (2 * box volume).
+fn sampled_area(solid: &Solid) -> f64 {
+    let b = solid.bbox();
+    if box_volume(&b) == 0.0 {
+        return 0.0;
+    }
+    let d = (b.max.0 - b.min.0, b.max.1 - b.min.1, b.max.2 - b.min.2);
+    let eps = MEASURE_SHELL * (d.0 * d.0 + d.1 * d.1 + d.2 * d.2).sqrt();
+    let padded = BBox::new(
+        (b.min.0 - eps, b.min.1 - eps, b.min.2 - eps),
+        (b.max.0 + eps, b.max.1 + eps, b.max.2 + eps),
+    );
+    let mut rng = Pcg64::seed_from_u64(MEASURE_SEED);
+    let crossings = (0..MEASURE_SAMPLES)
+        .filter(|_| {
+            let p = sample_point(&mut rng, &padded);
+            let z: f64 = rng.gen_range(-1.0, 1.0);
+            let th: f64 = rng.gen_range(0.0, 2.0 * PI);
+            let rho = (1.0 - z * z).sqrt();
+            let q = (
+                p.0 + eps * rho * th.cos(),
+                p.1 + eps * rho * th.sin(),
+                p.2 + eps * z,
+            );
+            solid.contains(p) != solid.contains(q)
+        })
+        .count();
+    2.0 * box_volume(&padded) * crossings as f64 / (MEASURE_SAMPLES as f64 * eps)
+}
@@ -7,11 +7,13 @@ use log::warn;
 use egg::{rewrite as rw, *};
 
 use crate::{
+    base::geom::{normalize_angle, rotation_angles, Affine3, BBox, Point},
     base::list_op::{Partitioning, Permutation},
     base::num::{num, Num},
-    cad::{Cad, EGraph, MetaAnalysis, Rewrite, Vec3},
+    cad::{Cad, EGraph, ListVar, MetaAnalysis, Rewrite, Vec3},
     hyperparameters::{
-        AFFINE_SIGNATURE_MAX_LEN, CAD_IDENTS, INV_TRANS, PARTITIONING, PARTITIONING_MAX,
+        ABS_EPSILON, AFFINE_SIGNATURE_MAX_LEN, ANTI_UNIFY_MAX_HOLES, BOOL_ALGEBRA, CAD_IDENTS,
+        COALESCE_MAX, INV_TRANS, PARTITIONING, PARTITIONING_MAX, ROTATE_DIGITS,
         STRUCTURE_MATCH_LIMIT,
     },
 };
@@ -43,20 +45,166 @@ fn is_pos(vars: &[&'static str]) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
     }
 }
 
+// A flat scale squashes different solids onto the same one, so it does not
+// commute with Inter and Diff.
+fn is_invertible(
+    aff: &'static str,
+    param: &'static str,
+) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
+    let aff: Var = aff.parse().unwrap();
+    let param: Var = param.parse().unwrap();
+    move |egraph, _, subst| {
+        if !egraph[subst[aff]].nodes.contains(&Cad::Scale) {
+            return true;
+        }
+        match get_vec(egraph, &egraph[subst[param]].data.best) {
+            Some((x, y, z)) => [x, y, z].iter().all(|n| n.to_f64() != 0.0),
+            None => false,
+        }
+    }
+}
+
+// Every affine commutes with Union, only the invertible ones with Inter and Diff.
+fn commutes_with(
+    bop: &'static str,
+    aff: &'static str,
+    param: &'static str,
+) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
+    let bop: Var = bop.parse().unwrap();
+    let invertible = is_invertible(aff, param);
+    move |egraph, id, subst| {
+        egraph[subst[bop]].nodes.contains(&Cad::Union) || invertible(egraph, id, subst)
+    }
+}
+
+fn is_disjoint(v1: &'static str, v2: &'static str) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
+    let v1: Var = v1.parse().unwrap();
+    let v2: Var = v2.parse().unwrap();
+    move |egraph, _, subst| match (egraph[subst[v1]].data.bbox, egraph[subst[v2]].data.bbox) {
+        (Some(b1), Some(b2)) => b1.is_disjoint(&b2, ABS_EPSILON),
+        _ => false,
+    }
+}
+
+// Whether two elements of the list have disjoint bounding boxes.
+fn has_disjoint_pair(list: &'static str) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
+    let list: Var = list.parse().unwrap();
+    move |egraph, _, subst| {
+        let boxes: Vec<BBox> = match &egraph[subst[list]].data.list {
+            Some(ids) => ids.iter().filter_map(|&id| egraph[id].data.bbox).collect(),
+            None => return false,
+        };
+        boxes
+            .iter()
+            .tuple_combinations()
+            .any(|(a, b)| a.is_disjoint(b, ABS_EPSILON))
+    }
+}
+
+// The length of the list a node stands for, found by evaluating the best terms below it.
+fn eval_len(egraph: &EGraph, node: &Cad) -> Option<usize> {
+    list_len(&node.build_recexpr(|id| egraph[id].data.best.clone()))
+}
+
+// The length of the list an expression evaluates to.
+fn list_len(expr: &RecExpr<Cad>) -> Option<usize> {
+    let mut out = RecExpr::default();
+    let root = (expr.as_ref().len() - 1).into();
+    let list = crate::eval::eval(None, expr, root, &mut out);
+    match &out[list] {
+        Cad::List(l) => Some(l.len()),
+        _ => None,
+    }
+}
+
+// Add the best term of an e-class to `out`.
+fn add_best(egraph: &EGraph, id: Id, out: &mut RecExpr<Cad>) -> Id {
+    let node = egraph[id].data.best.clone();
+    let node = node.map_children(|c| add_best(egraph, c, out));
+    out.add(node)
+}
+
+// The points of a list of vectors, found by evaluating the best terms below it.
+fn eval_points(egraph: &EGraph, node: &Cad) -> Option<Vec<Point>> {
+    let expr = node.build_recexpr(|id| egraph[id].data.best.clone());
+    let mut out = RecExpr::default();
+    let root = (expr.as_ref().len() - 1).into();
+    let list = crate::eval::eval(None, &expr, root, &mut out);
+    let num = |id: Id| match &out[id] {
+        Cad::Num(n) => Some(n.to_f64()),
+        _ => None,
+    };
+    match &out[list] {
+        Cad::List(l) => l
+            .iter()
+            .map(|&v| match &out[v] {
+                Cad::Vec3([x, y, z]) => Some((num(*x)?, num(*y)?, num(*z)?)),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+// A MapI with these bounds has `n` elements. The bounds may depend on the outer loop
+// variables, so count by evaluating them, in an expression of their own, since a condition
+// must not add to the e-graph it searches.
+fn is_count(n: &'static str, bounds: &[&'static str]) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
+    let n: Var = n.parse().unwrap();
+    let bounds: Vec<Var> = bounds.iter().map(|v| v.parse().unwrap()).collect();
+    move |egraph, _, subst| {
+        let n = match get_float(&egraph[subst[n]].data.best) {
+            Some(n) => n.to_f64(),
+            None => return false,
+        };
+        let mut expr = RecExpr::default();
+        let mut args: Vec<Id> = bounds
+            .iter()
+            .map(|v| add_best(egraph, subst[*v], &mut expr))
+            .collect();
+        args.push(expr.add(Cad::Num(num(0.0))));
+        expr.add(Cad::MapI(args));
+        list_len(&expr).is_some_and(|len| len as f64 == n)
+    }
+}
+
+// Union and Inter can be regrouped, Diff cannot
+fn is_assoc(var: &'static str) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
+    let var: Var = var.parse().unwrap();
+    move |egraph, _, subst| !egraph[subst[var]].nodes.contains(&Cad::Diff)
+}
+
 #[rustfmt::skip]
 pub fn pre_rules() -> Vec<Rewrite> {
     vec![
         rw!("union_comm"; "(Binop Union ?a ?b)" => "(Binop Union ?b ?a)"),
         rw!("inter_comm"; "(Binop Inter ?a ?b)" => "(Binop Inter ?b ?a)"),
         rw!("binary_op_fold"; "(Binop ?bop ?a ?b)" => "(Fold ?bop (List ?a ?b))"),
-        rw!("fold_cons"; "(Binop ?bop ?a (Fold ?bop ?list))" => "(Fold ?bop (Cons ?a ?list))"),
+        rw!("fold_cons"; "(Binop ?bop ?a (Fold ?bop ?list))" => "(Fold ?bop (Cons ?a ?list))"
+            if is_assoc("?bop")),
 
         rw!(
             "flatten_union";
             "(Fold Union ?list)" => {
                 let list = "?list".parse().unwrap();
                 let op = Cad::Union;
-                Flatten { list, op }
+                Flatten { list, op: op.clone(), rest: op }
+            }
+        ),
+        rw!(
+            "flatten_inter";
+            "(Fold Inter ?list)" => {
+                let list = "?list".parse().unwrap();
+                let op = Cad::Inter;
+                Flatten { list, op: op.clone(), rest: op }
+            }
+        ),
+        // a - b - c = a - (b + c), so only the head of a Diff is a Diff
+        rw!(
+            "flatten_diff";
+            "(Fold Diff ?list)" => {
+                let list = "?list".parse().unwrap();
+                Flatten { list, op: Cad::Diff, rest: Cad::Union }
             }
         ),
     ]
@@ -98,11 +246,23 @@ pub fn cad_rules() -> Vec<Rewrite> {
     // rules related to the CAD domain
     vec![
         // Getting shared operation out
-        rw!("fold_repeat"; "(Fold ?bop (Map2 ?aff (Repeat ?n ?param) ?cads))"=> "(Affine ?aff ?param (Fold ?bop ?cads))"),
-
-        rw!("fold_op"; "(Fold ?bop (Affine ?aff ?param ?cad))"=> "(Affine ?aff ?param (Fold ?bop ?cad))"),
-
-        rw!("union_trans"; "(Binop Union (Affine Trans (Vec3 ?x ?y ?z) ?a) (Affine Trans (Vec3 ?x ?y ?z) ?b))"=> "(Affine Trans (Vec3 ?x ?y ?z) (Binop Union ?a ?b))"),
+        rw!("fold_repeat"; "(Fold ?bop (Map2 ?aff (Repeat ?n ?param) ?cads))"=> "(Affine ?aff ?param (Fold ?bop ?cads))"
+            if commutes_with("?bop", "?aff", "?param")),
+
+        rw!("fold_op"; "(Fold ?bop (Affine ?aff ?param ?cad))"=> "(Affine ?aff ?param (Fold ?bop ?cad))"
+            if commutes_with("?bop", "?aff", "?param")),
+        rw!("fold_concat2"; "(Fold ?bop (Concat (List ?a ?b)))"=> "(Binop ?bop (Fold ?bop ?a) (Fold ?bop ?b))"
+            if is_assoc("?bop")),
+        rw!("fold_concat3"; "(Fold ?bop (Concat (List ?a ?b ?c)))"=> "(Binop ?bop (Fold ?bop ?a) (Binop ?bop (Fold ?bop ?b) (Fold ?bop ?c)))"
+            if is_assoc("?bop")),
+        rw!("fold_concat_mapi"; "(Fold ?bop (Concat (MapI ?n ?x)))"=> "(Fold ?bop (MapI ?n (Fold ?bop ?x)))"
+            if is_assoc("?bop")),
+
+        rw!("union_affine"; "(Binop Union (Affine ?aff ?p ?a) (Affine ?aff ?p ?b))"=> "(Affine ?aff ?p (Binop Union ?a ?b))"),
+        rw!("inter_affine"; "(Binop Inter (Affine ?aff ?p ?a) (Affine ?aff ?p ?b))"=> "(Affine ?aff ?p (Binop Inter ?a ?b))"
+            if is_invertible("?aff", "?p")),
+        rw!("diff_affine"; "(Binop Diff (Affine ?aff ?p ?a) (Affine ?aff ?p ?b))"=> "(Affine ?aff ?p (Binop Diff ?a ?b))"
+            if is_invertible("?aff", "?p")),
 
         // Related to Boolean Operators
         rw!("union_same"; "(Binop Union ?a ?a)"=> "?a"),
@@ -110,6 +270,26 @@ pub fn cad_rules() -> Vec<Rewrite> {
         rw!("inter_union"; "(Binop Inter ?a (Binop Union ?a ?b))"=> "?a"),
         rw!("inter_empty"; "(Binop Inter ?a Empty)"=> "Empty"),
 
+        // Guarded by bounding boxes
+        rw!("inter_disjoint"; "(Binop Inter ?a ?b)"=> "Empty"
+            if is_disjoint("?a", "?b")),
+        rw!("diff_disjoint"; "(Binop Diff ?a ?b)"=> "?a"
+            if is_disjoint("?a", "?b")),
+        // the same for the flat folds of pre_rules
+        rw!("inter_disjoint_fold"; "(Fold Inter ?list)"=> "Empty"
+            if has_disjoint_pair("?list")),
+        rw!("diff_disjoint_fold"; "(Fold Diff ?list)" => {
+            let list = "?list".parse().unwrap();
+            DropDisjoint { list }
+        }),
+        rw!("diff_split"; "(Fold Diff ?list)" => {
+            let list = "?list".parse().unwrap();
+            SplitDiff { list }
+        }),
+        rw!("coalesce_cubes"; "(Fold Union ?list)" => {
+            let list = "?list".parse().unwrap();
+            CoalesceCubes { list }
+        }),
         // MapI: aka Tabulate
         rw!("repeat_mapi"; "(Repeat ?n ?x)"=> "(MapI ?n ?x)"),
         rw!("map_repeat"; "(Map2 ?op (MapI ?n ?formula) (MapI ?n ?cad))"=> "(MapI ?n (Affine ?op ?formula ?cad))"),
@@ -118,6 +298,30 @@ pub fn cad_rules() -> Vec<Rewrite> {
             "(Map2 ?op (MapI ?n1 ?n2 ?formula) (Repeat ?n ?cad))" =>
             "(MapI ?n1 ?n2 (Affine ?op ?formula ?cad))"
             if is_eq("?n", "(* ?n1 ?n2)")),
+        rw!("map_mapi_counted";
+            "(Map2 ?op (MapI ?n1 ?formula) (Repeat ?n ?cad))" =>
+            "(MapI ?n1 (Affine ?op ?formula ?cad))"
+            if is_count("?n", &["?n1"])),
+        rw!("map_mapi2_rows";
+            "(Map2 ?op (MapI ?n1 ?n2 ?formula) (Repeat ?n ?cad))" =>
+            "(MapI ?n1 ?n2 (Affine ?op ?formula ?cad))"
+            if is_count("?n", &["?n1", "?n2"])),
+        // Iterate: an affine applied again and again
+        rw!("iterate_trans";
+            "(Iterate ?n Trans (Vec3 ?x ?y ?z) ?cads)" =>
+            "(Map2 Trans (MapI ?n (Vec3 (* ?x i) (* ?y i) (* ?z i))) ?cads)"),
+        rw!("iterate_scale";
+            "(Iterate ?n Scale (Vec3 ?x ?y ?z) ?cads)" =>
+            "(Map2 Scale (MapI ?n (Vec3 (pow ?x i) (pow ?y i) (pow ?z i))) ?cads)"),
+        rw!("iterate_rotate_x";
+            "(Iterate ?n Rotate (Vec3 ?a 0 0) ?cads)" =>
+            "(Map2 Rotate (MapI ?n (Vec3 (* ?a i) 0 0)) ?cads)"),
+        rw!("iterate_rotate_y";
+            "(Iterate ?n Rotate (Vec3 0 ?a 0) ?cads)" =>
+            "(Map2 Rotate (MapI ?n (Vec3 0 (* ?a i) 0)) ?cads)"),
+        rw!("iterate_rotate_z";
+            "(Iterate ?n Rotate (Vec3 0 0 ?a) ?cads)" =>
+            "(Map2 Rotate (MapI ?n (Vec3 0 0 (* ?a i))) ?cads)"),
         rw!("mapi2_mapi2"; "(Map2 ?op (MapI ?n1 ?n2 ?param) (MapI ?n1 ?n2 ?cad))"=> "(MapI ?n1 ?n2 (Affine ?op ?param ?cad))"),
 
         // partitioning
@@ -187,6 +391,34 @@ pub fn cad_identity_rules() -> Vec<Rewrite> {
         rw!("id"; "(Affine Trans (Vec3 0 0 0) ?a)"=> "?a"),
         rw!("combine_scale"; "(Affine Scale (Vec3 ?a ?b ?c) (Affine Scale (Vec3 ?d ?e ?f) ?cad))"=> "(Affine Scale (Vec3 (* ?a ?d) (* ?b ?e) (* ?c ?f)) ?cad)"),
         rw!("combine_trans"; "(Affine Trans (Vec3 ?a ?b ?c) (Affine Trans (Vec3 ?d ?e ?f) ?cad))"=> "(Affine Trans (Vec3 (+ ?a ?d) (+ ?b ?e) (+ ?c ?f)) ?cad)"),
+        // rotations about the same axis add up
+        rw!("combine_rotate_x"; "(Affine Rotate (Vec3 ?a 0 0) (Affine Rotate (Vec3 ?b 0 0) ?cad))"=> "(Affine Rotate (Vec3 (+ ?a ?b) 0 0) ?cad)"),
+        rw!("combine_rotate_y"; "(Affine Rotate (Vec3 0 ?a 0) (Affine Rotate (Vec3 0 ?b 0) ?cad))"=> "(Affine Rotate (Vec3 0 (+ ?a ?b) 0) ?cad)"),
+        rw!("combine_rotate_z"; "(Affine Rotate (Vec3 0 0 ?a) (Affine Rotate (Vec3 0 0 ?b) ?cad))"=> "(Affine Rotate (Vec3 0 0 (+ ?a ?b)) ?cad)"),
+        // constant rotations, composed and moved past translations numerically
+        rw!("combine_rotate"; "(Affine Rotate ?outer (Affine Rotate ?inner ?cad))" => {
+            let outer = "?outer".parse().unwrap();
+            let inner = "?inner".parse().unwrap();
+            let cad = "?cad".parse().unwrap();
+            RotateApplier { outer, inner, cad }
+        }),
+        rw!("normalize_rotate"; "(Affine Rotate ?angles ?cad)" => {
+            let angles = "?angles".parse().unwrap();
+            let cad = "?cad".parse().unwrap();
+            NormalizeRotateApplier { angles, cad }
+        }),
+        rw!("rotate_trans"; "(Affine Rotate ?angles (Affine Trans ?v ?cad))" => {
+            let angles = "?angles".parse().unwrap();
+            let v = "?v".parse().unwrap();
+            let cad = "?cad".parse().unwrap();
+            RotateTransApplier { angles, v, cad, trans_outside: true }
+        }),
+        rw!("trans_rotate"; "(Affine Trans ?v (Affine Rotate ?angles ?cad))" => {
+            let angles = "?angles".parse().unwrap();
+            let v = "?v".parse().unwrap();
+            let cad = "?cad".parse().unwrap();
+            RotateTransApplier { angles, v, cad, trans_outside: false }
+        }),
     ];
         
     // add the intro rules only for cads
@@ -216,6 +448,48 @@ pub fn cad_identity_rules() -> Vec<Rewrite> {
     rules
 }
 
+// Only the directions that factor a term out, so the e-graph does not grow.
+// There is no commutativity here, hence the mirrored variants.
+#[rustfmt::skip]
+pub fn bool_rules() -> Vec<Rewrite> {
+    vec![
+        // Empty
+        rw!("union_empty_l"; "(Binop Union Empty ?a)"=> "?a"),
+        rw!("union_empty_r"; "(Binop Union ?a Empty)"=> "?a"),
+        rw!("inter_empty_l"; "(Binop Inter Empty ?a)"=> "Empty"),
+        rw!("diff_empty_l"; "(Binop Diff Empty ?a)"=> "Empty"),
+        rw!("diff_empty_r"; "(Binop Diff ?a Empty)"=> "?a"),
+        rw!("diff_same"; "(Binop Diff ?a ?a)"=> "Empty"),
+        rw!("fold_nil"; "(Fold ?bop Nil)"=> "Empty"),
+        rw!("fold_single"; "(Fold ?bop (List ?a))"=> "?a"),
+        rw!("affine_empty"; "(Affine ?aff ?p Empty)"=> "Empty"),
+
+        // absorption
+        rw!("inter_union_r"; "(Binop Inter (Binop Union ?a ?b) ?a)"=> "?a"),
+        rw!("union_inter"; "(Binop Union ?a (Binop Inter ?a ?b))"=> "?a"),
+        rw!("union_inter_r"; "(Binop Union (Binop Inter ?a ?b) ?a)"=> "?a"),
+        rw!("union_diff"; "(Binop Union ?a (Binop Diff ?a ?b))"=> "?a"),
+        rw!("inter_diff"; "(Binop Inter ?a (Binop Diff ?a ?b))"=> "(Binop Diff ?a ?b)"),
+        rw!("diff_diff_same"; "(Binop Diff (Binop Diff ?a ?b) ?b)"=> "(Binop Diff ?a ?b)"),
+        rw!("diff_inter_same"; "(Binop Diff ?a (Binop Inter ?a ?b))"=> "(Binop Diff ?a ?b)"),
+        rw!("diff_union_same"; "(Binop Diff (Binop Union ?a ?b) ?b)"=> "(Binop Diff ?a ?b)"),
+        rw!("diff_then_inter"; "(Binop Inter (Binop Diff ?a ?b) ?b)"=> "Empty"),
+
+        // distributivity
+        rw!("inter_over_union"; "(Binop Union (Binop Inter ?a ?b) (Binop Inter ?a ?c))"=> "(Binop Inter ?a (Binop Union ?b ?c))"),
+        rw!("inter_over_union_r"; "(Binop Union (Binop Inter ?b ?a) (Binop Inter ?c ?a))"=> "(Binop Inter (Binop Union ?b ?c) ?a)"),
+        rw!("union_over_inter"; "(Binop Inter (Binop Union ?a ?b) (Binop Union ?a ?c))"=> "(Binop Union ?a (Binop Inter ?b ?c))"),
+        rw!("union_over_inter_r"; "(Binop Inter (Binop Union ?b ?a) (Binop Union ?c ?a))"=> "(Binop Union (Binop Inter ?b ?c) ?a)"),
+        rw!("diff_over_union"; "(Binop Union (Binop Diff ?a ?c) (Binop Diff ?b ?c))"=> "(Binop Diff (Binop Union ?a ?b) ?c)"),
+        rw!("diff_over_inter"; "(Binop Inter (Binop Diff ?a ?c) (Binop Diff ?b ?c))"=> "(Binop Diff (Binop Inter ?a ?b) ?c)"),
+
+        // De Morgan
+        rw!("diff_union_de_morgan"; "(Binop Inter (Binop Diff ?a ?b) (Binop Diff ?a ?c))"=> "(Binop Diff ?a (Binop Union ?b ?c))"),
+        rw!("diff_inter_de_morgan"; "(Binop Union (Binop Diff ?a ?b) (Binop Diff ?a ?c))"=> "(Binop Diff ?a (Binop Inter ?b ?c))"),
+        rw!("diff_diff"; "(Binop Diff (Binop Diff ?a ?b) ?c)"=> "(Binop Diff ?a (Binop Union ?b ?c))"),
+    ]
+}
+
 pub fn inv_trans_rules() -> Vec<Rewrite> {
     vec![
         rw!("map_unpart_r2";
@@ -253,6 +527,14 @@ pub fn inv_trans_rules() -> Vec<Rewrite> {
 
         // unpolar
         rw!("unpolar_trans"; "(Map2 Trans (Unpolar ?n ?center ?params) ?cads)"=> "(Map2 Trans (Repeat ?n ?center) (Map2 TransPolar ?params ?cads))"),
+        // positions rotated into a tilted plane: undo the rotation on the cads, which turns
+        // about x, then y, then z, place them, and rotate the result
+        rw!("map_trans_rotate";
+            "(Map2 Trans (Affine Rotate (Vec3 ?a ?b ?c) ?vs) ?cads)" =>
+            "(Affine Rotate (Vec3 ?a ?b ?c) (Map2 Trans ?vs
+               (Affine Rotate (Vec3 (- 0 ?a) 0 0)
+                 (Affine Rotate (Vec3 0 (- 0 ?b) 0)
+                   (Affine Rotate (Vec3 0 0 (- 0 ?c)) ?cads)))))"),
     ]
 }
 
@@ -273,6 +555,10 @@ pub fn rules() -> Vec<Rewrite> {
         rules.extend(cad_identity_rules());
     }
 
+    if BOOL_ALGEBRA {
+        rules.extend(bool_rules());
+    }
+
     rules.push(rw!(
         "listapplier";
         "?list" => {
@@ -319,6 +605,29 @@ pub fn rules() -> Vec<Rewrite> {
         }
     ));
 
+    rules.push(rw!(
+        "map_remove";
+        "(Map2 ?op (Remove ?params ?indices) (Repeat ?n ?cad))" => {
+            let op = "?op".parse().unwrap();
+            let params = "?params".parse().unwrap();
+            let indices = "?indices".parse().unwrap();
+            let n = "?n".parse().unwrap();
+            let cad = "?cad".parse().unwrap();
+            RemoveApplier { op, params, indices, n, cad }
+        }
+    ));
+
+    rules.push(rw!(
+        "mapi_iterate";
+        "(Map2 ?op (MapI ?n ?param) ?cads)" => {
+            let op = "?op".parse().unwrap();
+            let n = "?n".parse().unwrap();
+            let param = "?param".parse().unwrap();
+            let cads = "?cads".parse().unwrap();
+            IterateApplier { op, n, param, cads }
+        }
+    ));
+
     println!("Using {} rules", rules.len());
 
     rules
@@ -326,7 +635,7 @@ pub fn rules() -> Vec<Rewrite> {
 
 fn get_float(expr: &Cad) -> Option<Num> {
     match expr {
-        Cad::Num(f) => Some(*f),
+        Cad::Num(f) => Some(f.clone()),
         _ => None,
     }
 }
@@ -343,6 +652,82 @@ fn get_vec(egraph: &EGraph, expr: &Cad) -> Option<Vec3> {
     }
 }
 
+// A hole in an anti-unified template: the path to a numeric leaf and its value in each element.
+type Hole = (Vec<usize>, Vec<Num>);
+
+// The paths to the numeric leaves that differ between the elements, and their values.
+// This anti-unifies the best terms: the template is the best term of the first element with a
+// hole at every differing number. Fails if the elements differ in anything but numbers.
+fn varying_nums(egraph: &EGraph, ids: &[Id], holes: &mut Vec<Hole>, path: &mut Vec<usize>) -> bool {
+    let first = egraph.find(ids[0]);
+    if ids.iter().all(|&id| egraph.find(id) == first) {
+        return true;
+    }
+    if holes.len() >= ANTI_UNIFY_MAX_HOLES {
+        return false;
+    }
+
+    let bests: Vec<&Cad> = ids.iter().map(|&id| &egraph[id].data.best).collect();
+    let nums: Option<Vec<Num>> = bests.iter().map(|b| get_float(b)).collect();
+    if let Some(nums) = nums {
+        holes.push((path.clone(), nums));
+        return true;
+    }
+
+    let op = |b: &Cad| b.clone().map_children(|_| Id::from(0));
+    if bests.iter().any(|b| op(b) != op(bests[0])) {
+        return false;
+    }
+    (0..bests[0].children().len()).all(|pos| {
+        let children: Vec<Id> = bests.iter().map(|b| b.children()[pos]).collect();
+        path.push(pos);
+        let ok = varying_nums(egraph, &children, holes, path);
+        path.pop();
+        ok
+    })
+}
+
+// Rebuild the best term of `id` with the subterms at the given paths replaced.
+fn replace_at(egraph: &mut EGraph, id: Id, fills: &[(&[usize], Id)]) -> Id {
+    if let Some((_, new)) = fills.iter().find(|(path, _)| path.is_empty()) {
+        return *new;
+    }
+    if fills.is_empty() {
+        return id;
+    }
+    let mut node = egraph[id].data.best.clone();
+    for pos in 0..node.children().len() {
+        let inner: Vec<(&[usize], Id)> = fills
+            .iter()
+            .filter(|(path, _)| path[0] == pos)
+            .map(|&(path, new)| (&path[1..], new))
+            .collect();
+        let child = node.children()[pos];
+        node.children_mut()[pos] = replace_at(egraph, child, &inner);
+    }
+    egraph.add(node)
+}
+
+// A MapI over the elements, with each varying number given by a formula in i.
+fn insert_num_map(egraph: &mut EGraph, ids: &[Id]) -> Option<Id> {
+    let mut holes = vec![];
+    if !varying_nums(egraph, ids, &mut holes, &mut vec![]) || holes.is_empty() {
+        return None;
+    }
+    let bodies = holes
+        .iter()
+        .map(|(_, nums)| crate::solve::solve_nums_fn(egraph, nums))
+        .collect::<Option<Vec<Id>>>()?;
+    let fills: Vec<(&[usize], Id)> = holes
+        .iter()
+        .zip(bodies)
+        .map(|((path, _), body)| (path.as_slice(), body))
+        .collect();
+    let elem = replace_at(egraph, ids[0], &fills);
+    let n = egraph.add(Cad::Num(ids.len().into()));
+    Some(egraph.add(Cad::MapI(vec![n, elem])))
+}
+
 #[derive(Debug)]
 struct ListApplier {
     var: Var,
@@ -505,6 +890,481 @@ fn insert_map2s(egraph: &mut EGraph, list_ids: &[Id]) -> Vec<Id> {
     results
 }
 
+// The ways an element can be a translated rotation about z of `inner`:
+// (Affine Trans v (Affine Rotate (Vec3 0 0 a) inner)) as (v, a, inner).
+fn trans_rotates(egraph: &EGraph, id: Id) -> Vec<(Point, f64, Id)> {
+    let mut found = vec![];
+    for (v, rotated) in get_affines(egraph, id, &Cad::Trans) {
+        let v = match get_point(egraph, v) {
+            Some(v) => v,
+            None => continue,
+        };
+        for (angles, inner) in get_affines(egraph, rotated, &Cad::Rotate) {
+            if let Some((0.0, 0.0, a)) = get_point(egraph, angles) {
+                found.push((v, a, egraph.find(inner)));
+            }
+        }
+    }
+    found
+}
+
+// Detect copies of one shape rotated about a vertical axis by evenly spaced angles,
+// like a bolt circle, and write them as
+// (MapI n (Affine Trans c (Affine Rotate (Vec3 0 0 (+ (* d i) a0)) (Affine Trans u inner)))).
+fn insert_rotational(egraph: &mut EGraph, ids: &[Id]) -> Option<Id> {
+    let n = ids.len();
+    if n < 3 {
+        return None;
+    }
+    let elems: Vec<_> = ids.iter().map(|&id| trans_rotates(egraph, id)).collect();
+    let close = |a: f64, b: f64| num(a).is_close(b);
+
+    for &(_, _, inner) in &elems[0] {
+        // pick, for every element, a decomposition around the same inner shape
+        let picked: Option<Vec<(Point, f64)>> = elems
+            .iter()
+            .map(|cands| cands.iter().find(|c| c.2 == inner).map(|&(v, a, _)| (v, a)))
+            .collect();
+        let picked = match picked {
+            Some(picked) => picked,
+            None => continue,
+        };
+
+        // evenly spaced angles, going around once
+        let a0 = picked[0].1;
+        let d = normalize_angle(picked[1].1 - a0);
+        if !close(d.abs(), 360.0 / n as f64) {
+            continue;
+        }
+        let spaced = picked
+            .iter()
+            .enumerate()
+            .all(|(i, &(_, a))| close(normalize_angle(a - a0 - i as f64 * d), 0.0));
+        if !spaced {
+            continue;
+        }
+
+        // the offsets of a full ring average out, except along the axis
+        let sum = picked.iter().fold((0.0, 0.0, 0.0), |s, &(v, _)| {
+            (s.0 + v.0, s.1 + v.1, s.2 + v.2)
+        });
+        let nf = n as f64;
+        let c = (sum.0 / nf, sum.1 / nf, sum.2 / nf);
+        let v0 = picked[0].0;
+        let to_local = Affine3::rotate((0.0, 0.0, -a0));
+        let u = to_local.apply((v0.0 - c.0, v0.1 - c.1, v0.2 - c.2));
+        let on_ring = picked.iter().all(|&(v, a)| {
+            let p = Affine3::rotate((0.0, 0.0, a)).apply(u);
+            close(c.0 + p.0, v.0) && close(c.1 + p.1, v.1) && close(c.2 + p.2, v.2)
+        });
+        if !on_ring {
+            continue;
+        }
+
+        let trans = egraph.add(Cad::Trans);
+        let rotate = egraph.add(Cad::Rotate);
+        let u = add_rounded_vec(egraph, u);
+        let placed = egraph.add(Cad::Affine([trans, u, inner]));
+
+        let i = egraph.add(Cad::ListVar(ListVar("i")));
+        let d = egraph.add(Cad::Num(d.into()));
+        let a0 = egraph.add(Cad::Num(normalize_angle(a0).into()));
+        let di = egraph.add(Cad::Mul([d, i]));
+        let angle = egraph.add(Cad::Add([di, a0]));
+        let zero = egraph.add(Cad::Num(num(0.0)));
+        let angles = egraph.add(Cad::Vec3([zero, zero, angle]));
+        let rotated = egraph.add(Cad::Affine([rotate, angles, placed]));
+
+        let c = add_rounded_vec(egraph, c);
+        let centered = egraph.add(Cad::Affine([trans, c, rotated]));
+        let n = egraph.add(Cad::Num(n.into()));
+        return Some(egraph.add(Cad::MapI(vec![n, centered])));
+    }
+    None
+}
+
+fn axis_vec(axis: usize, len: f64) -> Point {
+    let mut v = [0.0; 3];
+    v[axis] = len;
+    (v[0], v[1], v[2])
+}
+
+// The reflection of `p` in the plane where coordinate `axis` is `c`.
+fn reflect(p: Point, axis: usize, c: f64) -> Point {
+    let mut v = [p.0, p.1, p.2];
+    v[axis] = 2.0 * c - v[axis];
+    (v[0], v[1], v[2])
+}
+
+// Find the mirror image of the best term of `id` in the plane where coordinate `axis` is `c`,
+// written without a Mirror node. It is only looked up, since a partner element is already in
+// the egraph, and nothing is added for elements without one.
+fn mirror_term(egraph: &EGraph, id: Id, axis: usize, c: f64) -> Option<Id> {
+    let (v, shape) = mirror_placed(egraph, id, axis, c)?;
+    if v == (0.0, 0.0, 0.0) {
+        return Some(shape);
+    }
+    let trans = egraph.lookup(Cad::Trans)?;
+    let v = lookup_rounded_vec(egraph, v)?;
+    egraph.lookup(Cad::Affine([trans, v, shape]))
+}
+
+// The mirror image as a translation of a shape, so that nested translations add up
+// to the single one the partner element has.
+fn mirror_placed(egraph: &EGraph, id: Id, axis: usize, c: f64) -> Option<(Point, Id)> {
+    let add = |a: Point, b: Point| (a.0 + b.0, a.1 + b.1, a.2 + b.2);
+    let best = egraph[id].data.best.clone();
+    if let Cad::Affine([op, p, inner]) = best {
+        if egraph[op].data.best == Cad::Trans {
+            let p = get_point(egraph, p)?;
+            let (v, shape) = mirror_placed(egraph, inner, axis, 0.0)?;
+            return Some((add(reflect(p, axis, c), v), shape));
+        }
+    }
+    // reflecting about c is reflecting about 0, then moving by 2c
+    let moved = axis_vec(axis, 2.0 * c);
+
+    let centered = |egraph: &EGraph, b: Id| egraph[b].data.best == Cad::Bool(true);
+    match best {
+        Cad::Empty | Cad::Sphere(_) => Some((moved, id)),
+        Cad::Cube([size, center]) => {
+            if centered(egraph, center) {
+                return Some((moved, id));
+            }
+            let s = get_point(egraph, size)?;
+            Some((add(moved, axis_vec(axis, -[s.0, s.1, s.2][axis])), id))
+        }
+        Cad::Cylinder([dims, params, center]) => {
+            if axis != 2 {
+                return Some((moved, id));
+            }
+            let h = match &egraph[dims].data.best {
+                Cad::Vec3([h, r1, r2]) => [*h, *r1, *r2],
+                _ => return None,
+            };
+            let flipped = if egraph.find(h[1]) == egraph.find(h[2]) {
+                id
+            } else {
+                let dims = egraph.lookup(Cad::Vec3([h[0], h[2], h[1]]))?;
+                egraph.lookup(Cad::Cylinder([dims, params, center]))?
+            };
+            if centered(egraph, center) {
+                return Some((moved, flipped));
+            }
+            let h = get_float(&egraph[h[0]].data.best)?.to_f64();
+            Some((add(moved, axis_vec(axis, -h)), flipped))
+        }
+        Cad::Affine([op, s, inner]) if egraph[op].data.best == Cad::Scale => {
+            // axis-aligned scaling commutes with the reflection
+            let inner = mirror_term(egraph, inner, axis, 0.0)?;
+            Some((moved, egraph.lookup(Cad::Affine([op, s, inner]))?))
+        }
+        Cad::Binop([op, a, b]) => {
+            let a = mirror_term(egraph, a, axis, 0.0)?;
+            let b = mirror_term(egraph, b, axis, 0.0)?;
+            Some((moved, egraph.lookup(Cad::Binop([op, a, b]))?))
+        }
+        Cad::Fold([op, list]) => {
+            let ids = match &egraph[list].data.best {
+                Cad::List(ids) => ids.clone(),
+                _ => return None,
+            };
+            let ids = ids
+                .into_iter()
+                .map(|id| mirror_term(egraph, id, axis, 0.0))
+                .collect::<Option<Vec<_>>>()?;
+            let list = egraph.lookup(Cad::List(ids))?;
+            Some((moved, egraph.lookup(Cad::Fold([op, list]))?))
+        }
+        _ => None,
+    }
+}
+
+// Pair up the elements that are mirror images of each other in the plane where
+// coordinate `axis` is `c`. An element that is its own mirror image is its own partner.
+fn mirror_partners(
+    egraph: &EGraph,
+    ids: &[Id],
+    boxes: &[BBox],
+    axis: usize,
+    c: f64,
+) -> Option<Vec<usize>> {
+    let close = |a: &BBox, b: &BBox| {
+        let (a, b) = ([a.min, a.max], [b.min, b.max]);
+        (0..2).all(|k| {
+            num(a[k].0).is_close(b[k].0)
+                && num(a[k].1).is_close(b[k].1)
+                && num(a[k].2).is_close(b[k].2)
+        })
+    };
+    let mirrored: Vec<BBox> = boxes
+        .iter()
+        .map(|b| BBox::new(reflect(b.min, axis, c), reflect(b.max, axis, c)))
+        .collect();
+    // cheap test on the bounding boxes before looking up any terms
+    if !mirrored.iter().all(|m| boxes.iter().any(|b| close(m, b))) {
+        return None;
+    }
+
+    let mut partner: Vec<Option<usize>> = vec![None; ids.len()];
+    for i in 0..ids.len() {
+        if partner[i].is_some() {
+            continue;
+        }
+        let image = mirror_term(egraph, ids[i], axis, c)?;
+        let image = egraph.find(image);
+        let j = (i..ids.len()).find(|&j| {
+            partner[j].is_none() && close(&mirrored[i], &boxes[j]) && egraph.find(ids[j]) == image
+        })?;
+        partner[i] = Some(j);
+        partner[j] = Some(i);
+    }
+    partner.into_iter().collect()
+}
+
+// The map of the chain of Trans, Rotate and Scale at the top of a best term, and the
+// e-class it is applied to.
+fn affine_chain(egraph: &EGraph, mut id: Id) -> Option<(Affine3, Id)> {
+    let mut m = Affine3::identity();
+    while let Cad::Affine([op, p, inner]) = egraph[id].data.best {
+        let p = get_point(egraph, p)?;
+        let a = match egraph[op].data.best {
+            Cad::Trans => Affine3::translate(p),
+            Cad::Rotate => Affine3::rotate(p),
+            Cad::Scale => Affine3::scale(p),
+            _ => break,
+        };
+        m = a.then(&m);
+        id = inner;
+    }
+    Some((m, egraph.find(id)))
+}
+
+// Detect copies where each one is the previous one moved by the same similarity, like a
+// spiral of shrinking shapes. The step scales uniformly by s and rotates about an axis
+// through c, then moves along that axis, so the list is
+// (Affine Trans c (Iterate n Trans t (Iterate n Rotate a (Iterate n Scale s (Repeat n first - c))))).
+fn insert_iterated(egraph: &mut EGraph, ids: &[Id]) -> Option<Id> {
+    let n = ids.len();
+    if n < 3 {
+        return None;
+    }
+    let chains = ids
+        .iter()
+        .map(|&id| affine_chain(egraph, id))
+        .collect::<Option<Vec<_>>>()?;
+    if chains.iter().any(|&(_, inner)| inner != chains[0].1) {
+        return None;
+    }
+    let step = chains[0].0.inverse()?.then(&chains[1].0);
+
+    let close = |a: f64, b: f64| num(a).is_close(b);
+    let same_point = |p: Point, q: Point| close(p.0, q.0) && close(p.1, q.1) && close(p.2, q.2);
+    let same_map = |a: &Affine3, b: &Affine3| {
+        (0..3).all(|i| (0..3).all(|j| close(a.lin[i][j], b.lin[i][j]))) && same_point(a.off, b.off)
+    };
+    let mut m = chains[0].0;
+    for (k, (mk, _)) in chains.iter().enumerate() {
+        if k > 0 {
+            m = m.then(&step);
+        }
+        if !same_map(&m, mk) {
+            return None;
+        }
+    }
+
+    // split the linear part into a uniform scaling and a rotation about one axis
+    let s = step.det().cbrt();
+    if s <= 0.0 {
+        return None;
+    }
+    let mut q = step.lin;
+    q.iter_mut().flatten().for_each(|v| *v /= s);
+    let angles = rotation_angles(&q)?;
+    let angles = (
+        normalize_angle(angles.0),
+        normalize_angle(angles.1),
+        normalize_angle(angles.2),
+    );
+    let turning: Vec<usize> = (0..3)
+        .filter(|&a| !close([angles.0, angles.1, angles.2][a], 0.0))
+        .collect();
+    if turning.len() > 1 {
+        return None;
+    }
+    let (scaled, turned) = (!close(s, 1.0), !turning.is_empty());
+    if !scaled && !turned {
+        // steps that only move are plain in a MapI already
+        return None;
+    }
+
+    // the centre c solves (I - lin) c = t; a step that only turns may also move along
+    // the axis, and then the centre is taken in the plane through the origin
+    let t = step.off;
+    let mut lin = [[0.0; 3]; 3];
+    for (i, row) in lin.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = if i == j { 1.0 } else { 0.0 } - step.lin[i][j];
+        }
+    }
+    let (along, center) = if scaled {
+        ((0.0, 0.0, 0.0), Affine3::linear(lin).inverse()?.apply(t))
+    } else {
+        let axis = turning[0];
+        let mut v = [0.0; 3];
+        v[axis] = [t.0, t.1, t.2][axis];
+        lin[axis] = [0.0; 3];
+        lin[axis][axis] = 1.0;
+        let (along, across) = ((v[0], v[1], v[2]), (t.0 - v[0], t.1 - v[1], t.2 - v[2]));
+        (along, Affine3::linear(lin).inverse()?.apply(across))
+    };
+
+    let trans = egraph.add(Cad::Trans);
+    let nid = egraph.add(Cad::Num(n.into()));
+    let first = match egraph[ids[0]].data.best {
+        Cad::Affine([op, p, inner]) if egraph[op].data.best == Cad::Trans => {
+            let p = get_point(egraph, p)?;
+            let p = add_rounded_vec(egraph, (p.0 - center.0, p.1 - center.1, p.2 - center.2));
+            egraph.add(Cad::Affine([trans, p, inner]))
+        }
+        _ if same_point(center, (0.0, 0.0, 0.0)) => ids[0],
+        _ => {
+            let c = add_rounded_vec(egraph, (-center.0, -center.1, -center.2));
+            egraph.add(Cad::Affine([trans, c, ids[0]]))
+        }
+    };
+    let mut list = egraph.add(Cad::Repeat([nid, first]));
+    let iterate = |egraph: &mut EGraph, op: Cad, param: Point, list: Id| {
+        let op = egraph.add(op);
+        let param = add_rounded_vec(egraph, param);
+        egraph.add(Cad::Iterate([nid, op, param, list]))
+    };
+    if scaled {
+        list = iterate(egraph, Cad::Scale, (s, s, s), list);
+    }
+    if turned {
+        list = iterate(egraph, Cad::Rotate, angles, list);
+    }
+    if !same_point(along, (0.0, 0.0, 0.0)) {
+        list = iterate(egraph, Cad::Trans, along, list);
+    }
+    if !same_point(center, (0.0, 0.0, 0.0)) {
+        let c = add_rounded_vec(egraph, center);
+        list = egraph.add(Cad::Affine([trans, c, list]));
+    }
+    Some(list)
+}
+
+// Detect a list whose elements are mirror images of each other in pairs, across a plane
+// normal to an axis through the middle of the list, and write it as
+// (Concat (List fixed half (Affine Trans 2c (Affine Mirror axis half)))).
+// Since extraction counts `half` twice in that form, also write the two halves as
+// (MapI 2 (Affine Trans (* 2c i) (Affine Scale (pow -1 i) half))), where the scale
+// is 1 along the other axes, so the second copy is reflected by a scale of -1.
+fn insert_mirrored(egraph: &mut EGraph, ids: &[Id]) -> Vec<Id> {
+    if ids.len() < 2 {
+        return vec![];
+    }
+    // copies of one shape at different places are left to the solvers
+    let shape = |id: Id| match egraph[id].data.best {
+        Cad::Affine([op, _, inner]) if egraph[op].data.best == Cad::Trans => egraph.find(inner),
+        _ => egraph.find(id),
+    };
+    if ids.iter().all(|&id| shape(id) == shape(ids[0])) {
+        return vec![];
+    }
+    let boxes = match ids
+        .iter()
+        .map(|&id| egraph[id].data.bbox.filter(|b| !b.is_empty()))
+        .collect::<Option<Vec<BBox>>>()
+    {
+        Some(boxes) => boxes,
+        None => return vec![],
+    };
+    let whole = boxes.iter().fold(BBox::empty(), |a, b| a.union(b));
+    let (lo, hi) = (
+        [whole.min.0, whole.min.1, whole.min.2],
+        [whole.max.0, whole.max.1, whole.max.2],
+    );
+    let scale = 10f64.powi(ROTATE_DIGITS);
+
+    for axis in 0..3 {
+        let c = ((lo[axis] + hi[axis]) / 2.0 * scale).round() / scale + 0.0;
+        let partner = match mirror_partners(egraph, ids, &boxes, axis, c) {
+            Some(partner) => partner,
+            None => continue,
+        };
+        let fixed: Vec<usize> = (0..ids.len()).filter(|&i| partner[i] == i).collect();
+        let firsts: Vec<usize> = (0..ids.len()).filter(|&i| partner[i] > i).collect();
+        if firsts.is_empty() || fixed.len() > firsts.len() {
+            continue;
+        }
+
+        let trans = egraph.add(Cad::Trans);
+        let mirror = egraph.add(Cad::Mirror);
+        let half = egraph.add(Cad::List(firsts.iter().map(|&i| ids[i]).collect()));
+        let fixed_list = match fixed.is_empty() {
+            true => None,
+            false => Some(egraph.add(Cad::List(fixed.iter().map(|&i| ids[i]).collect()))),
+        };
+
+        let normal = add_rounded_vec(egraph, axis_vec(axis, 1.0));
+        let mut mirrored = egraph.add(Cad::Affine([mirror, normal, half]));
+        if c != 0.0 {
+            let v = add_rounded_vec(egraph, axis_vec(axis, 2.0 * c));
+            mirrored = egraph.add(Cad::Affine([trans, v, mirrored]));
+        }
+        let parts: Vec<Id> = fixed_list.into_iter().chain([half, mirrored]).collect();
+        let parts = egraph.add(Cad::List(parts));
+        let concat = egraph.add(Cad::Concat([parts]));
+
+        // the same, with a single copy of the half
+        let i = egraph.add(Cad::ListVar(ListVar("i")));
+        let zero = egraph.add(Cad::Num(num(0.0)));
+        let one = egraph.add(Cad::Num(num(1.0)));
+        let minus_one = egraph.add(Cad::Num(num(-1.0)));
+        let along = |egraph: &mut EGraph, rest: Id, x: Id| {
+            let mut v = [rest; 3];
+            v[axis] = x;
+            egraph.add(Cad::Vec3(v))
+        };
+        let sign = egraph.add(Cad::Pow([minus_one, i]));
+        let factors = along(egraph, one, sign);
+        let scale = egraph.add(Cad::Scale);
+        let mut body = egraph.add(Cad::Affine([scale, factors, half]));
+        if c != 0.0 {
+            let d = egraph.add(Cad::Num((2.0 * c).into()));
+            let di = egraph.add(Cad::Mul([d, i]));
+            let v = along(egraph, zero, di);
+            body = egraph.add(Cad::Affine([trans, v, body]));
+        }
+        let two = egraph.add(Cad::Num(num(2.0)));
+        let mapi = egraph.add(Cad::MapI(vec![two, body]));
+        let mut shared = egraph.add(Cad::Concat([mapi]));
+        if let Some(fixed_list) = fixed_list {
+            let parts = egraph.add(Cad::List(vec![fixed_list, shared]));
+            shared = egraph.add(Cad::Concat([parts]));
+        }
+
+        let order: Vec<usize> = fixed
+            .iter()
+            .chain(&firsts)
+            .copied()
+            .chain(firsts.iter().map(|&i| partner[i]))
+            .collect();
+        let perm = Permutation::from_vec(&order);
+        if perm.is_ordered() {
+            return vec![concat, shared];
+        }
+        let perm = egraph.add(Cad::Permutation(perm));
+        return [concat, shared]
+            .iter()
+            .map(|&list| egraph.add(Cad::Unsort([perm, list])))
+            .collect();
+    }
+    vec![]
+}
+
 #[allow(dead_code)]
 fn num_sign(n: Num) -> i32 {
     let f = n.to_f64();
@@ -533,7 +1393,7 @@ impl Applier<Cad, MetaAnalysis> for ListApplier {
         eclass: Id,
         map: &Subst,
         _searcher_ast: Option<&PatternAst<Cad>>,
-        _rule_name: Symbol,
+        rule_name: Symbol,
     ) -> Vec<Id> {
         let ids: Vec<Id> = get_meta_list!(egraph, map[self.var]).clone();
         let bests: Vec<_> = ids.iter().map(|&id| egraph[id].data.best.clone()).collect();
@@ -558,7 +1418,7 @@ impl Applier<Cad, MetaAnalysis> for ListApplier {
                 results.push(id);
 
                 for result in results.iter() {
-                    egraph.union(eclass, *result);
+                    egraph.union_trusted(eclass, *result, rule_name);
                 }
                 return results;
             }
@@ -570,12 +1430,16 @@ impl Applier<Cad, MetaAnalysis> for ListApplier {
             .any(|&id| egraph[id].nodes.iter().any(|n| matches!(n, Cad::List(_))))
         {
             for result in results.iter() {
-                egraph.union(eclass, *result);
+                egraph.union_trusted(eclass, *result, rule_name);
             }
             return results;
         }
 
         results.extend(insert_map2s(egraph, &ids));
+        results.extend(insert_rotational(egraph, &ids));
+        results.extend(insert_num_map(egraph, &ids));
+        results.extend(insert_mirrored(egraph, &ids));
+        results.extend(insert_iterated(egraph, &ids));
 
         // try to solve a list
         if let Some(vec_list) = bests
@@ -588,17 +1452,17 @@ impl Applier<Cad, MetaAnalysis> for ListApplier {
                 let solved = crate::solve::solve(egraph, &vec_list);
                 results.extend(solved);
             }
-            results.extend(partition_list(egraph, &ids, |i, _| vec_list[i].0));
-            results.extend(partition_list(egraph, &ids, |i, _| vec_list[i].1));
-            results.extend(partition_list(egraph, &ids, |i, _| vec_list[i].2));
+            results.extend(partition_list(egraph, &ids, |i, _| vec_list[i].0.clone()));
+            results.extend(partition_list(egraph, &ids, |i, _| vec_list[i].1.clone()));
+            results.extend(partition_list(egraph, &ids, |i, _| vec_list[i].2.clone()));
             results.extend(partition_list(egraph, &ids, |i, _| {
-                (vec_list[i].0, vec_list[i].1)
+                (vec_list[i].0.clone(), vec_list[i].1.clone())
             }));
             results.extend(partition_list(egraph, &ids, |i, _| {
-                (vec_list[i].0, vec_list[i].2)
+                (vec_list[i].0.clone(), vec_list[i].2.clone())
             }));
             results.extend(partition_list(egraph, &ids, |i, _| {
-                (vec_list[i].1, vec_list[i].2)
+                (vec_list[i].1.clone(), vec_list[i].2.clone())
             }));
         }
 
@@ -611,7 +1475,7 @@ impl Applier<Cad, MetaAnalysis> for ListApplier {
         }
 
         for result in results.iter() {
-            egraph.union(eclass, *result);
+            egraph.union_trusted(eclass, *result, rule_name);
         }
         results
     }
@@ -641,7 +1505,7 @@ impl Applier<Cad, MetaAnalysis> for SortApplier {
         eclass: Id,
         map: &Subst,
         _searcher_ast: Option<&PatternAst<Cad>>,
-        _rule_name: Symbol,
+        rule_name: Symbol,
     ) -> Vec<Id> {
         let items = get_meta_list!(egraph, map[self.list]);
         let perm: &Permutation = get_unit!(egraph, map[self.perm], Cad::Permutation);
@@ -649,7 +1513,120 @@ impl Applier<Cad, MetaAnalysis> for SortApplier {
         let e = Cad::List(sorted);
 
         let id = egraph.add(e);
-        egraph.union(eclass, id);
+        egraph.union_trusted(eclass, id, rule_name);
+        vec![id]
+    }
+}
+
+// (Map2 op (Remove params indices) (Repeat n cad)) =>
+// (Remove (Map2 op params (Repeat m cad)) indices), with m the length of params
+#[derive(Debug)]
+struct RemoveApplier {
+    op: Var,
+    params: Var,
+    indices: Var,
+    n: Var,
+    cad: Var,
+}
+
+impl Applier<Cad, MetaAnalysis> for RemoveApplier {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph,
+        eclass: Id,
+        map: &Subst,
+        _searcher_ast: Option<&PatternAst<Cad>>,
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let indices: Option<Vec<Num>> = match &egraph[map[self.indices]].data.best {
+            Cad::List(ids) => ids
+                .iter()
+                .map(|&i| get_float(&egraph[i].data.best))
+                .collect(),
+            _ => None,
+        };
+        let m = eval_len(egraph, &egraph[map[self.params]].data.best);
+        let (indices, m) = match (indices, m) {
+            (Some(indices), Some(m)) => (indices, m),
+            _ => return vec![],
+        };
+        // whole indices into the params, each counted once
+        let mut removed = vec![];
+        for i in indices {
+            let whole = i.to_f64().round();
+            if whole < 0.0 || whole >= m as f64 || !i.is_close(whole) {
+                return vec![];
+            }
+            removed.push(whole as usize);
+        }
+        removed.sort_unstable();
+        removed.dedup();
+        let n = get_float(&egraph[map[self.n]].data.best).map(|n| n.to_f64());
+        if n != Some((m - removed.len()) as f64) {
+            return vec![];
+        }
+
+        let m = egraph.add(Cad::Num(m.into()));
+        let repeat = egraph.add(Cad::Repeat([m, map[self.cad]]));
+        let map2 = egraph.add(Cad::Map2([map[self.op], map[self.params], repeat]));
+        let id = egraph.add(Cad::Remove([map2, map[self.indices]]));
+        egraph.union_trusted(eclass, id, rule_name);
+        vec![id]
+    }
+}
+
+// (Map2 op (MapI n param) cads) => (Iterate n op step cads), when the k-th param is the
+// second one to the power k for a Scale, or k times the second one for a Rotate about one
+// axis. A composed affine is a Map2 of a Map2, and becomes an Iterate of an Iterate, like
+// (Iterate n Rotate (Vec3 0 0 a) (Iterate n Scale (Vec3 s s s) cads)). Moves are linear
+// in i and plain in a MapI already, so they are not rewritten.
+#[derive(Debug)]
+struct IterateApplier {
+    op: Var,
+    n: Var,
+    param: Var,
+    cads: Var,
+}
+
+impl Applier<Cad, MetaAnalysis> for IterateApplier {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph,
+        eclass: Id,
+        map: &Subst,
+        _searcher_ast: Option<&PatternAst<Cad>>,
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let mapi = Cad::MapI(vec![map[self.n], map[self.param]]);
+        // a plain count, not a Range
+        let n = get_float(&egraph[map[self.n]].data.best).map(|n| n.to_f64());
+        let params = match eval_points(egraph, &mapi) {
+            Some(params) if params.len() > 2 && n == Some(params.len() as f64) => params,
+            _ => return vec![],
+        };
+        let close = |a: f64, b: f64| num(a).is_close(b);
+        let step = params[1];
+        let axes = [step.0, step.1, step.2]
+            .iter()
+            .filter(|&&a| a != 0.0)
+            .count();
+        let (kth, identity): (fn(usize, f64) -> f64, _) = match egraph[map[self.op]].data.best {
+            Cad::Scale => (|k, s| s.powi(k as i32), (1.0, 1.0, 1.0)),
+            // turns about more than one axis do not add up
+            Cad::Rotate if axes < 2 => (|k, a| k as f64 * a, (0.0, 0.0, 0.0)),
+            _ => return vec![],
+        };
+        let iterated = params.iter().enumerate().all(|(k, p)| {
+            close(p.0, kth(k, step.0)) && close(p.1, kth(k, step.1)) && close(p.2, kth(k, step.2))
+        });
+        if !iterated || step == identity {
+            return vec![];
+        }
+
+        let (n, op, cads) = (map[self.n], map[self.op], map[self.cads]);
+        let step = add_rounded_vec(egraph, step);
+        let id = egraph.add(Cad::Iterate([n, op, step, cads]));
+        egraph.union_trusted(eclass, id, rule_name);
         vec![id]
     }
 }
@@ -667,7 +1644,7 @@ impl Applier<Cad, MetaAnalysis> for PartApplier {
         eclass: Id,
         map: &Subst,
         _searcher_ast: Option<&PatternAst<Cad>>,
-        _rule_name: Symbol,
+        rule_name: Symbol,
     ) -> Vec<Id> {
         let items = get_meta_list!(egraph, map[self.list]);
         let part: &Partitioning = get_unit!(egraph, map[self.part], Cad::Partitioning);
@@ -680,7 +1657,7 @@ impl Applier<Cad, MetaAnalysis> for PartApplier {
         let e = Cad::List(list_of_lists);
 
         let id = egraph.add(e);
-        egraph.union(eclass, id);
+        egraph.union_trusted(eclass, id, rule_name);
         vec![id]
     }
 }
@@ -698,7 +1675,7 @@ impl Applier<Cad, MetaAnalysis> for UnpartApplier {
         eclass: Id,
         map: &Subst,
         _searcher_ast: Option<&PatternAst<Cad>>,
-        _rule_name: Symbol,
+        rule_name: Symbol,
     ) -> Vec<Id> {
         let items = get_meta_list!(egraph, map[self.list]).clone();
         let part: Partitioning = get_unit!(egraph, map[self.part], Cad::Partitioning).clone();
@@ -706,7 +1683,7 @@ impl Applier<Cad, MetaAnalysis> for UnpartApplier {
 
         if items.is_empty() {
             let nil = egraph.add(Cad::Nil);
-            egraph.union(eclass, nil);
+            egraph.union_trusted(eclass, nil, rule_name);
             return vec![nil];
         }
 
@@ -752,7 +1729,7 @@ impl Applier<Cad, MetaAnalysis> for UnpartApplier {
             vec![unsort]
         };
         for result in results.iter() {
-            egraph.union(eclass, *result);
+            egraph.union_trusted(eclass, *result, rule_name);
         }
         results
     }
@@ -772,7 +1749,7 @@ impl Applier<Cad, MetaAnalysis> for SortUnpartApplier {
         eclass: Id,
         map: &Subst,
         _searcher_ast: Option<&PatternAst<Cad>>,
-        _rule_name: Symbol,
+        rule_name: Symbol,
     ) -> Vec<Id> {
         let sort: Permutation = get_unit!(egraph, map[self.sort], Cad::Permutation).clone();
         let part: Partitioning = get_unit!(egraph, map[self.part], Cad::Partitioning).clone();
@@ -807,15 +1784,18 @@ impl Applier<Cad, MetaAnalysis> for SortUnpartApplier {
 
         let results = vec![egraph.add(Cad::Unpart([part_id, list]))];
         for result in results.iter() {
-            egraph.union(eclass, *result);
+            egraph.union_trusted(eclass, *result, rule_name);
         }
         results
     }
 }
 
+// Splices nested folds into the list: a fold of `op` at the head, folds of `rest` anywhere
+// after it.
 #[derive(Debug)]
 struct Flatten {
     op: Cad,
+    rest: Cad,
     list: Var,
 }
 
@@ -826,7 +1806,7 @@ impl Applier<Cad, MetaAnalysis> for Flatten {
         eclass: Id,
         map: &Subst,
         _searcher_ast: Option<&PatternAst<Cad>>,
-        _rule_name: Symbol,
+        rule_name: Symbol,
     ) -> Vec<Id> {
         fn get_nested_fold<'a>(egraph: &'a EGraph, op: &'a Cad, id: Id) -> Option<&'a [Id]> {
             let is_op = |i| egraph[i].nodes.iter().any(|c| c == op);
@@ -840,10 +1820,12 @@ impl Applier<Cad, MetaAnalysis> for Flatten {
 
         let ids = get_meta_list!(egraph, map[self.list]);
         let mut new_ids = Vec::new();
-        for id in ids {
-            match get_nested_fold(egraph, &self.op, *id) {
-                Some(ids) => new_ids.extend(ids.iter().copied()),
-                None => new_ids.push(*id),
+        for (i, id) in ids.iter().enumerate() {
+            let op = if i == 0 { &self.op } else { &self.rest };
+            // an empty Inter or Diff is empty, not neutral
+            match get_nested_fold(egraph, op, *id) {
+                Some(ids) if !ids.is_empty() => new_ids.extend(ids.iter().copied()),
+                _ => new_ids.push(*id),
             }
         }
 
@@ -853,8 +1835,511 @@ impl Applier<Cad, MetaAnalysis> for Flatten {
 
         let results = vec![new_fold];
         for result in results.iter() {
-            egraph.union(eclass, *result);
+            egraph.union_trusted(eclass, *result, rule_name);
         }
         results
     }
 }
+
+// Fold Diff (List a b c ..) => Binop Diff a (Fold Union (List b c ..)),
+// so that what is cut out is a list of its own for the list rules to work on.
+#[derive(Debug)]
+struct SplitDiff {
+    list: Var,
+}
+
+impl Applier<Cad, MetaAnalysis> for SplitDiff {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph,
+        eclass: Id,
+        map: &Subst,
+        _searcher_ast: Option<&PatternAst<Cad>>,
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let ids = get_meta_list!(egraph, map[self.list]).clone();
+        if ids.len() < 3 {
+            return vec![];
+        }
+
+        let holes = egraph.add(Cad::List(ids[1..].to_vec()));
+        let union = egraph.add(Cad::Union);
+        let holes = egraph.add(Cad::Fold([union, holes]));
+        let diff = egraph.add(Cad::Diff);
+        let result = egraph.add(Cad::Binop([diff, ids[0], holes]));
+        egraph.union_trusted(eclass, result, rule_name);
+        vec![result]
+    }
+}
+
+// Drop the subtrahends of a Diff whose boxes miss the head's, and the whole Diff if none
+// is left.
+#[derive(Debug)]
+struct DropDisjoint {
+    list: Var,
+}
+
+impl Applier<Cad, MetaAnalysis> for DropDisjoint {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph,
+        eclass: Id,
+        map: &Subst,
+        _searcher_ast: Option<&PatternAst<Cad>>,
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let ids = get_meta_list!(egraph, map[self.list]).clone();
+        let head = match ids.first().and_then(|&id| egraph[id].data.bbox) {
+            Some(head) => head,
+            None => return vec![],
+        };
+        let kept: Vec<Id> = ids[1..]
+            .iter()
+            .copied()
+            .filter(|&id| match egraph[id].data.bbox {
+                Some(b) => !b.is_disjoint(&head, ABS_EPSILON),
+                None => true,
+            })
+            .collect();
+        if kept.len() + 1 == ids.len() {
+            return vec![];
+        }
+
+        let result = if kept.is_empty() {
+            ids[0]
+        } else {
+            let list = egraph.add(Cad::List([&ids[..1], &kept[..]].concat()));
+            let diff = egraph.add(Cad::Diff);
+            egraph.add(Cad::Fold([diff, list]))
+        };
+        egraph.union_trusted(eclass, result, rule_name);
+        vec![result]
+    }
+}
+
+// Merge the axis-aligned cubes of a union into fewer boxes: one drops a box inside
+// another, two boxes with the same extent along two axes that touch or overlap along the
+// third become one. Other elements are kept as they are.
+#[derive(Debug)]
+struct CoalesceCubes {
+    list: Var,
+}
+
+// The box a Cube under Trans and Scale (and quarter turns) fills, if it is one.
+fn cube_box(egraph: &EGraph, id: Id) -> Option<BBox> {
+    let (m, inner) = affine_chain(egraph, id)?;
+    let axis_aligned = m
+        .lin
+        .iter()
+        .all(|row| row.iter().filter(|x| x.abs() > 1e-9).count() == 1);
+    if !axis_aligned {
+        return None;
+    }
+    let (s, centered) = match egraph[inner].data.best {
+        Cad::Cube([size, center]) => (
+            get_point(egraph, size)?,
+            matches!(egraph[center].data.best, Cad::Bool(true)),
+        ),
+        _ => return None,
+    };
+    if s.0 <= 0.0 || s.1 <= 0.0 || s.2 <= 0.0 {
+        return None;
+    }
+    let local = if centered {
+        let h = (s.0 / 2.0, s.1 / 2.0, s.2 / 2.0);
+        BBox::new((-h.0, -h.1, -h.2), h)
+    } else {
+        BBox::new((0.0, 0.0, 0.0), s)
+    };
+    let corners = local.corners().map(|c| m.apply(c));
+    Some(BBox::from_points(corners))
+}
+
+// The union of the two boxes, if it is a box.
+fn merge_boxes(a: &BBox, b: &BBox) -> Option<BBox> {
+    let axes = |b: &BBox| [(b.min.0, b.max.0), (b.min.1, b.max.1), (b.min.2, b.max.2)];
+    let close = |x: f64, y: f64| (x - y).abs() <= ABS_EPSILON;
+    let within = |(amin, amax): (f64, f64), (bmin, bmax): (f64, f64)| {
+        amin + ABS_EPSILON >= bmin && amax <= bmax + ABS_EPSILON
+    };
+    let (ia, ib) = (axes(a), axes(b));
+    if (0..3).all(|k| within(ia[k], ib[k])) {
+        return Some(*b);
+    }
+    if (0..3).all(|k| within(ib[k], ia[k])) {
+        return Some(*a);
+    }
+    // faces are shared only up to rounding noise, and the boxes must meet or overlap,
+    // so that no gap is ever filled
+    let same = (0..3)
+        .filter(|&k| close(ia[k].0, ib[k].0) && close(ia[k].1, ib[k].1))
+        .count();
+    let touch = (0..3).all(|k| ia[k].1 >= ib[k].0 && ib[k].1 >= ia[k].0);
+    (same == 2 && touch).then(|| a.union(b))
+}
+
+impl Applier<Cad, MetaAnalysis> for CoalesceCubes {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph,
+        eclass: Id,
+        map: &Subst,
+        _searcher_ast: Option<&PatternAst<Cad>>,
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let ids = get_meta_list!(egraph, map[self.list]).clone();
+        if ids.len() < 2 || ids.len() > COALESCE_MAX {
+            return vec![];
+        }
+
+        let mut boxes = vec![];
+        let mut others = vec![];
+        for id in ids {
+            match cube_box(egraph, id) {
+                Some(b) => boxes.push(b),
+                None => others.push(id),
+            }
+        }
+        let before = boxes.len();
+        'merge: loop {
+            for i in 0..boxes.len() {
+                for j in i + 1..boxes.len() {
+                    if let Some(b) = merge_boxes(&boxes[i], &boxes[j]) {
+                        boxes[i] = b;
+                        boxes.remove(j);
+                        continue 'merge;
+                    }
+                }
+            }
+            break;
+        }
+        if boxes.len() == before {
+            return vec![];
+        }
+
+        let trans = egraph.add(Cad::Trans);
+        let no_center = egraph.add(Cad::Bool(false));
+        let mut new_ids = vec![];
+        for b in boxes {
+            let size = (b.max.0 - b.min.0, b.max.1 - b.min.1, b.max.2 - b.min.2);
+            let size = add_rounded_vec(egraph, size);
+            let cube = egraph.add(Cad::Cube([size, no_center]));
+            let min = add_rounded_vec(egraph, b.min);
+            new_ids.push(egraph.add(Cad::Affine([trans, min, cube])));
+        }
+        new_ids.extend(others);
+
+        let list = egraph.add(Cad::List(new_ids));
+        let union = egraph.add(Cad::Union);
+        let result = egraph.add(Cad::Fold([union, list]));
+        egraph.union_trusted(eclass, result, rule_name);
+        vec![result]
+    }
+}
+
+fn get_point(egraph: &EGraph, id: Id) -> Option<Point> {
+    let v = get_vec(egraph, &egraph[id].data.best)?;
+    Some((v.0.to_f64(), v.1.to_f64(), v.2.to_f64()))
+}
+
+// Round to ROTATE_DIGITS decimals, so that the same vector computed along
+// different paths becomes the same e-node.
+fn add_rounded_vec(egraph: &mut EGraph, p: Point) -> Id {
+    let scale = 10f64.powi(ROTATE_DIGITS);
+    let mut add = |v: f64| {
+        let r = (v * scale).round() / scale;
+        // no negative zero
+        egraph.add(Cad::Num((r + 0.0).into()))
+    };
+    let x = add(p.0);
+    let y = add(p.1);
+    let z = add(p.2);
+    egraph.add(Cad::Vec3([x, y, z]))
+}
+
+fn lookup_rounded_vec(egraph: &EGraph, p: Point) -> Option<Id> {
+    let scale = 10f64.powi(ROTATE_DIGITS);
+    let lookup = |v: f64| {
+        let r = (v * scale).round() / scale;
+        egraph.lookup(Cad::Num((r + 0.0).into()))
+    };
+    let v = [lookup(p.0)?, lookup(p.1)?, lookup(p.2)?];
+    egraph.lookup(Cad::Vec3(v))
+}
+
+fn add_rotate(egraph: &mut EGraph, angles: Point, cad: Id) -> Id {
+    let angles = (
+        normalize_angle(angles.0),
+        normalize_angle(angles.1),
+        normalize_angle(angles.2),
+    );
+    let rotate = egraph.add(Cad::Rotate);
+    let angles = add_rounded_vec(egraph, angles);
+    egraph.add(Cad::Affine([rotate, angles, cad]))
+}
+
+#[derive(Debug)]
+struct RotateApplier {
+    outer: Var,
+    inner: Var,
+    cad: Var,
+}
+
+impl Applier<Cad, MetaAnalysis> for RotateApplier {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph,
+        eclass: Id,
+        map: &Subst,
+        _searcher_ast: Option<&PatternAst<Cad>>,
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let (outer, inner) = match (
+            get_point(egraph, map[self.outer]),
+            get_point(egraph, map[self.inner]),
+        ) {
+            (Some(outer), Some(inner)) => (outer, inner),
+            _ => return vec![],
+        };
+        let m = Affine3::rotate(inner).then(&Affine3::rotate(outer)).lin;
+        let angles = match rotation_angles(&m) {
+            Some(angles) => angles,
+            None => return vec![],
+        };
+
+        let id = add_rotate(egraph, angles, map[self.cad]);
+        egraph.union_trusted(eclass, id, rule_name);
+        vec![id]
+    }
+}
+
+#[derive(Debug)]
+struct NormalizeRotateApplier {
+    angles: Var,
+    cad: Var,
+}
+
+impl Applier<Cad, MetaAnalysis> for NormalizeRotateApplier {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph,
+        eclass: Id,
+        map: &Subst,
+        _searcher_ast: Option<&PatternAst<Cad>>,
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let angles = match get_point(egraph, map[self.angles]) {
+            Some(angles) => angles,
+            None => return vec![],
+        };
+        let in_range = |a: f64| -180.0 < a && a <= 180.0;
+        if in_range(angles.0) && in_range(angles.1) && in_range(angles.2) {
+            return vec![];
+        }
+
+        let id = add_rotate(egraph, angles, map[self.cad]);
+        egraph.union_trusted(eclass, id, rule_name);
+        vec![id]
+    }
+}
+
+/// Swaps a constant rotation and a constant translation,
+/// rotating the translation vector to compensate.
+#[derive(Debug)]
+struct RotateTransApplier {
+    angles: Var,
+    v: Var,
+    cad: Var,
+    /// whether the result has the translation on the outside
+    trans_outside: bool,
+}
+
+impl Applier<Cad, MetaAnalysis> for RotateTransApplier {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph,
+        eclass: Id,
+        map: &Subst,
+        _searcher_ast: Option<&PatternAst<Cad>>,
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let (angles, v) = match (
+            get_point(egraph, map[self.angles]),
+            get_point(egraph, map[self.v]),
+        ) {
+            (Some(angles), Some(v)) => (angles, v),
+            _ => return vec![],
+        };
+        let rotation = Affine3::rotate(angles);
+
+        let trans = egraph.add(Cad::Trans);
+        let rotate = egraph.add(Cad::Rotate);
+        let id = if self.trans_outside {
+            // R (v + p) = R v + R p
+            let v = add_rounded_vec(egraph, rotation.apply(v));
+            let rotated = egraph.add(Cad::Affine([rotate, map[self.angles], map[self.cad]]));
+            egraph.add(Cad::Affine([trans, v, rotated]))
+        } else {
+            // v + R p = R (R^-1 v + p)
+            let v = match rotation.inverse() {
+                Some(inv) => add_rounded_vec(egraph, inv.apply(v)),
+                None => return vec![],
+            };
+            let moved = egraph.add(Cad::Affine([trans, v, map[self.cad]]));
+            egraph.add(Cad::Affine([rotate, map[self.angles], moved]))
+        };
+        egraph.union_trusted(eclass, id, rule_name);
+        vec![id]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_only_boxes_that_meet() {
+        let cube = |x: f64, y: f64| BBox::new((x, y, 0.0), (x + 1.0, y + 1.0, 1.0));
+        let merged = BBox::new((0.0, 0.0, 0.0), (2.0, 1.0, 1.0));
+        assert_eq!(merge_boxes(&cube(0.0, 0.0), &cube(1.0, 0.0)), Some(merged));
+
+        // a small gap, and faces that are only close relative to their size
+        assert_eq!(merge_boxes(&cube(0.0, 0.0), &cube(1.00005, 0.0)), None);
+        assert_eq!(merge_boxes(&cube(0.0, 10000.0), &cube(1.0, 10000.5)), None);
+    }
+
+    #[test]
+    fn count_leaves_the_egraph() {
+        let mut egraph = EGraph::new(MetaAnalysis);
+        let n = egraph.add_expr(&"6".parse().unwrap());
+        let rows = egraph.add_expr(&"3".parse().unwrap());
+        let cols = egraph.add_expr(&"(+ i 1)".parse().unwrap());
+        egraph.rebuild();
+        let nodes = egraph.total_number_of_nodes();
+
+        let mut subst = Subst::default();
+        subst.insert("?n".parse().unwrap(), n);
+        subst.insert("?n1".parse().unwrap(), rows);
+        subst.insert("?n2".parse().unwrap(), cols);
+        // rows of 1, 2 and 3
+        assert!(is_count("?n", &["?n1", "?n2"])(&mut egraph, n, &subst));
+        assert!(!is_count("?n", &["?n1"])(&mut egraph, n, &subst));
+        assert_eq!(egraph.total_number_of_nodes(), nodes);
+    }
+
+    fn same_class(rule_names: &[&str], start: &str, other: &str) -> bool {
+        let rules: Vec<Rewrite> = pre_rules()
+            .into_iter()
+            .chain(rules())
+            .filter(|r| rule_names.contains(&r.name.as_str()))
+            .collect();
+        let start: RecExpr<Cad> = start.parse().unwrap();
+        let runner = Runner::default().with_expr(&start).run(&rules);
+        let other = runner.egraph.lookup_expr(&other.parse().unwrap());
+        other == Some(runner.egraph.find(runner.roots[0]))
+    }
+
+    #[test]
+    fn disjoint_folds() {
+        let cube = |x: i32| format!("(Affine Trans (Vec3 {} 0 0) (Cube (Vec3 2 2 2) false))", x);
+        let (a, b, far) = (cube(0), cube(1), cube(10));
+
+        let diff = format!("(Fold Diff (List {} {} {}))", a, b, far);
+        let kept = format!("(Fold Diff (List {} {}))", a, b);
+        assert!(same_class(&["diff_disjoint_fold"], &diff, &kept));
+        let diff = format!("(Fold Diff (List {} {}))", a, far);
+        assert!(same_class(&["diff_disjoint_fold"], &diff, &a));
+
+        let inter = format!("(Fold Inter (List {} {} {}))", a, b, far);
+        assert!(same_class(&["inter_disjoint_fold"], &inter, "Empty"));
+        // overlapping, or touching within the epsilon
+        let inter = format!("(Fold Inter (List {} {} {}))", a, b, cube(2));
+        assert!(!same_class(&["inter_disjoint_fold"], &inter, "Empty"));
+    }
+
+    #[test]
+    fn remove_counts_valid_indices() {
+        let cube = "(Cube (Vec3 1 1 1) false)";
+        let removed = |indices: &str, n: usize| {
+            let params = "(MapI 4 (Vec3 (* 2 i) 0 0))";
+            let start = format!(
+                "(Map2 Trans (Remove {} (List {})) (Repeat {} {}))",
+                params, indices, n, cube
+            );
+            let out = format!(
+                "(Remove (Map2 Trans {} (Repeat 4 {})) (List {}))",
+                params, cube, indices
+            );
+            same_class(&["map_remove"], &start, &out)
+        };
+        assert!(removed("1", 3));
+        assert!(removed("1 1", 3));
+        assert!(removed("0 3", 2));
+        // two removed of four leave two, not three; and no index past the end or before it
+        assert!(!removed("1 1", 2));
+        assert!(!removed("4", 3));
+        assert!(!removed("-1", 3));
+        assert!(!removed("0.5", 3));
+    }
+
+    #[test]
+    fn flatten_nested_diff() {
+        let cube = |x: i32| format!("(Affine Trans (Vec3 {} 0 0) (Cube (Vec3 2 2 2) false))", x);
+        let (a, b, c, d) = (cube(0), cube(1), cube(2), cube(3));
+        let diff = format!("(Fold Diff (List {} {}))", c, d);
+        let nested = format!(
+            "(Fold Diff (List (Fold Diff (List {} {})) (Fold Union (List {} {})) {}))",
+            a, b, c, d, diff
+        );
+
+        // (a - b) - (c + d) - (c - d) = a - b - c - d - (c - d)
+        let flat = format!("(Fold Diff (List {} {} {} {} {}))", a, b, c, d, diff);
+        assert!(same_class(&["flatten_diff"], &nested, &flat));
+        // but not a - b - c - d - c + d
+        let spliced = format!("(Fold Diff (List {} {} {} {} {} {}))", a, b, c, d, c, d);
+        assert!(!same_class(&["flatten_diff"], &nested, &spliced));
+    }
+
+    #[test]
+    fn fold_op_needs_invertible_affine() {
+        let list = "(List (Cube (Vec3 1 1 1) false) (Sphere 1 (Vec3 0 0 0)))";
+        let pulled = |bop: &str, s: &str| {
+            let start = format!("(Fold {} (Affine Scale (Vec3 {}) {}))", bop, s, list);
+            let out = format!("(Affine Scale (Vec3 {}) (Fold {} {}))", s, bop, list);
+            same_class(&["fold_op"], &start, &out)
+        };
+        assert!(pulled("Union", "1 1 0"));
+        assert!(pulled("Inter", "1 1 2"));
+        assert!(!pulled("Inter", "1 1 0"));
+        assert!(!pulled("Diff", "0 1 1"));
+    }
+
+    fn iterated(start: &str) -> EGraph {
+        let rule: Vec<Rewrite> = rules()
+            .into_iter()
+            .filter(|r| r.name.as_str() == "mapi_iterate")
+            .collect();
+        let start: RecExpr<Cad> = start.parse().unwrap();
+        let runner = Runner::default().with_expr(&start).run(&rule);
+        runner.egraph
+    }
+
+    #[test]
+    fn iterate_composed_affines() {
+        let egraph = iterated(
+            "(Map2 Rotate (MapI 4 (Vec3 0 0 (* 30 i)))
+               (Map2 Scale (MapI 4 (Vec3 (pow 0.8 i) (pow 0.8 i) (pow 0.8 i)))
+                 (Repeat 4 (Cube (Vec3 1 1 1) false))))",
+        );
+        let composed = "(Iterate 4 Rotate (Vec3 0 0 30)
+                          (Iterate 4 Scale (Vec3 0.8 0.8 0.8) (Repeat 4 (Cube (Vec3 1 1 1) false))))";
+        assert!(egraph.lookup_expr(&composed.parse().unwrap()).is_some());
+
+        // a turn about two axes is not the k-th power of the first one
+        let egraph = iterated(
+            "(Map2 Rotate (MapI 4 (Vec3 (* 30 i) (* 30 i) 0)) (Repeat 4 (Cube (Vec3 1 1 1) false)))",
+        );
+        let turned = "(Iterate 4 Rotate (Vec3 30 30 0) (Repeat 4 (Cube (Vec3 1 1 1) false)))";
+        assert!(egraph.lookup_expr(&turned.parse().unwrap()).is_none());
+    }
+}
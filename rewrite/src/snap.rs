@@ -0,0 +1,110 @@
+/// Snap
+/// Merge the e-classes of numbers that differ by at most `ABS_EPSILON`, so that float noise
+/// from exporters does not keep equal values apart. Meant to run as a `Runner` hook.
+use egg::{Id, IterationData, Runner};
+
+use crate::base::num::Num;
+use crate::cad::{Cad, EGraph, MetaAnalysis};
+use crate::constant::recognize_constants;
+use crate::hyperparameters::{ABS_EPSILON, CONSTANTS, SNAP_NUMS};
+
+/// The hook for numbers, to install with `Runner::with_hook`: merge nearly equal numbers,
+/// then add closed forms of long ones, as turned on in `hyperparameters`.
+pub fn number_hook<D>(runner: &mut Runner<Cad, MetaAnalysis, D>) -> Result<(), String>
+where
+    D: IterationData<Cad, MetaAnalysis>,
+{
+    if SNAP_NUMS {
+        snap_nums(&mut runner.egraph);
+    }
+    if CONSTANTS {
+        recognize_constants(&mut runner.egraph);
+    }
+    Ok(())
+}
+
+/// Returns the number of unions made. The e-graph is rebuilt if there were any.
+pub fn snap_nums(egraph: &mut EGraph) -> usize {
+    let mut nums: Vec<(Num, Id)> = egraph
+        .classes()
+        .filter_map(|c| match &c.data.best {
+            Cad::Num(n) => Some((n.clone(), c.id)),
+            _ => None,
+        })
+        .collect();
+    nums.sort();
+
+    let mut unions = 0;
+    let mut start = 0;
+    while start < nums.len() {
+        // a run of numbers close to the smallest one, by an absolute epsilon only: a relative
+        // one would merge 10000 and 10001, which is geometry and not noise
+        let first = nums[start].0.to_f64();
+        let len = nums[start..]
+            .iter()
+            .take_while(|(n, _)| n.to_f64() - first <= ABS_EPSILON)
+            .count();
+        let end = start + len;
+        let run = &nums[start..end];
+
+        // the one that is shortest to print, which the cost function prefers too
+        let canonical = run
+            .iter()
+            .min_by_key(|(n, _)| n.to_string().len())
+            .map(|&(_, id)| id)
+            .unwrap();
+        // even a union of one e-class with itself leaves the e-graph dirty
+        for &(_, id) in run.iter().filter(|&&(_, id)| id != canonical) {
+            egraph.union_trusted(canonical, id, "snap_nums");
+            unions += 1;
+        }
+        start = end;
+    }
+
+    if unions > 0 {
+        egraph.rebuild();
+    }
+    unions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cad::MetaAnalysis;
+
+    #[test]
+    fn snaps_to_the_shortest() {
+        let mut egraph = EGraph::new(MetaAnalysis);
+        let a = egraph.add_expr(&"(Vec3 10.000001 2 3)".parse().unwrap());
+        let b = egraph.add_expr(&"(Vec3 10 2 3)".parse().unwrap());
+        let c = egraph.add_expr(&"(Vec3 10.5 2 3)".parse().unwrap());
+        let d = egraph.add_expr(&"(Vec3 10000 2 3)".parse().unwrap());
+        let e = egraph.add_expr(&"(Vec3 10000.5 2 3)".parse().unwrap());
+        egraph.rebuild();
+
+        assert_eq!(snap_nums(&mut egraph), 1);
+        assert_eq!(egraph.find(a), egraph.find(b));
+        assert_ne!(egraph.find(a), egraph.find(c));
+        assert_ne!(egraph.find(d), egraph.find(e));
+        let x = match egraph[a].data.best {
+            Cad::Vec3([x, _, _]) => x,
+            ref best => panic!("expected a vector, got {:?}", best),
+        };
+        assert_eq!(egraph[x].data.best, Cad::Num(10.0.into()));
+    }
+
+    #[test]
+    #[cfg(feature = "exact")]
+    fn exact_keeps_close_nums_apart() {
+        let expr = "(List 1/3 0.33333 100/3 33.333333)".parse().unwrap();
+        let runner = Runner::<Cad, MetaAnalysis, ()>::new(MetaAnalysis)
+            .with_expr(&expr)
+            .with_hook(number_hook)
+            .run(&[]);
+        let egraph = &runner.egraph;
+        let class = |s: &str| egraph.lookup_expr(&s.parse().unwrap()).unwrap();
+
+        assert_ne!(class("1/3"), class("0.33333"));
+        assert_ne!(class("100/3"), class("33.333333"));
+    }
+}
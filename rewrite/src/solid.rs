@@ -0,0 +1,178 @@
+/// Solid
+/// A point-membership view of a CAD program. The program is flattened with `eval`, every chain
+/// of affines is folded into a single map per primitive, and booleans are kept as a tree.
+use egg::{Id, RecExpr};
+
+use crate::base::geom::{to_cartesian, Affine3, BBox, Point};
+use crate::cad::Cad;
+use crate::cad_struct::{get_num, get_vec3_nums};
+use crate::eval::eval;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Prim {
+    /// The box `[0, size]`.
+    Cube(Point),
+    /// The ball of the given radius about the origin.
+    Sphere(f64),
+    /// The frustum along z from 0 to `h`, with radius `r1` at the bottom and `r2` at the top.
+    Cylinder { h: f64, r1: f64, r2: f64 },
+}
+
+impl Prim {
+    pub fn contains(&self, p: Point) -> bool {
+        match *self {
+            Prim::Cube(s) => {
+                let within = |v: f64, s: f64| 0.0 <= v && v <= s;
+                within(p.0, s.0) && within(p.1, s.1) && within(p.2, s.2)
+            }
+            Prim::Sphere(r) => p.0 * p.0 + p.1 * p.1 + p.2 * p.2 <= r * r,
+            Prim::Cylinder { h, r1, r2 } => {
+                if p.2 < 0.0 || p.2 > h {
+                    return false;
+                }
+                let r = r1 + (r2 - r1) * p.2 / h;
+                p.0 * p.0 + p.1 * p.1 <= r * r
+            }
+        }
+    }
+
+    pub fn bbox(&self) -> BBox {
+        match *self {
+            Prim::Cube(s) => BBox::new((0.0, 0.0, 0.0), s),
+            Prim::Sphere(r) => BBox::new((-r, -r, -r), (r, r, r)),
+            Prim::Cylinder { h, r1, r2 } => {
+                let r = r1.abs().max(r2.abs());
+                BBox::new((-r, -r, 0.0), (r, r, h))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Solid {
+    Empty,
+    /// A primitive placed by `to_world`; `to_local` is its inverse.
+    Prim {
+        prim: Prim,
+        to_world: Affine3,
+        to_local: Affine3,
+    },
+    Union(Vec<Solid>),
+    Inter(Vec<Solid>),
+    Diff(Vec<Solid>),
+}
+
+impl Solid {
+    /// Returns `None` if the program uses something without a point-membership test,
+    /// like `Hull` or a black box.
+    pub fn new(expr: &RecExpr<Cad>) -> Option<Solid> {
+        let mut normalform = RecExpr::from(vec![]);
+        let root = (expr.as_ref().len() - 1).into();
+        let p = eval(None, expr, root, &mut normalform);
+        Solid::from_normalform(&normalform, p)
+    }
+
+    /// Like `new`, for a subterm of an expression that is already in normal form.
+    pub fn from_normalform(expr: &RecExpr<Cad>, p: Id) -> Option<Solid> {
+        Solid::placed(expr, p, &Affine3::identity())
+    }
+
+    fn place(prim: Prim, to_world: Affine3) -> Solid {
+        // a degenerate map squashes the primitive to nothing
+        match to_world.inverse() {
+            Some(to_local) => Solid::Prim {
+                prim,
+                to_world,
+                to_local,
+            },
+            None => Solid::Empty,
+        }
+    }
+
+    fn placed(expr: &RecExpr<Cad>, p: Id, to_world: &Affine3) -> Option<Solid> {
+        let is_centered = |id: Id| matches!(expr[id], Cad::Bool(true));
+        match &expr[p] {
+            Cad::Empty => Some(Solid::Empty),
+            Cad::Cube(args) => {
+                let s = get_vec3_nums(expr, args[0]);
+                let local = if is_centered(args[1]) {
+                    Affine3::translate((-s.0 / 2.0, -s.1 / 2.0, -s.2 / 2.0))
+                } else {
+                    Affine3::identity()
+                };
+                Some(Solid::place(Prim::Cube(s), local.then(to_world)))
+            }
+            Cad::Sphere(args) => {
+                let r = get_num(expr, args[0]);
+                Some(Solid::place(Prim::Sphere(r), *to_world))
+            }
+            Cad::Cylinder(args) => {
+                let (h, r1, r2) = get_vec3_nums(expr, args[0]);
+                let local = if is_centered(args[2]) {
+                    Affine3::translate((0.0, 0.0, -h / 2.0))
+                } else {
+                    Affine3::identity()
+                };
+                let prim = Prim::Cylinder { h, r1, r2 };
+                Some(Solid::place(prim, local.then(to_world)))
+            }
+            Cad::Affine(args) => {
+                let v = get_vec3_nums(expr, args[1]);
+                let aff = match expr[args[0]] {
+                    Cad::Trans => Affine3::translate(v),
+                    Cad::TransPolar => Affine3::translate(to_cartesian(v)),
+                    Cad::Scale => Affine3::scale(v),
+                    Cad::Rotate => Affine3::rotate(v),
+                    Cad::Mirror => Affine3::mirror(v).unwrap_or_else(Affine3::identity),
+                    _ => return None,
+                };
+                Solid::placed(expr, args[2], &aff.then(to_world))
+            }
+            Cad::Fold(args) => {
+                let children = match &expr[args[1]] {
+                    Cad::List(list) => list
+                        .iter()
+                        .map(|&c| Solid::placed(expr, c, to_world))
+                        .collect::<Option<Vec<_>>>()?,
+                    _ => return None,
+                };
+                match expr[args[0]] {
+                    Cad::Union => Some(Solid::Union(children)),
+                    Cad::Inter => Some(Solid::Inter(children)),
+                    Cad::Diff => Some(Solid::Diff(children)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, p: Point) -> bool {
+        match self {
+            Solid::Empty => false,
+            Solid::Prim { prim, to_local, .. } => prim.contains(to_local.apply(p)),
+            Solid::Union(cs) => cs.iter().any(|c| c.contains(p)),
+            Solid::Inter(cs) => !cs.is_empty() && cs.iter().all(|c| c.contains(p)),
+            Solid::Diff(cs) => match cs.split_first() {
+                Some((first, rest)) => first.contains(p) && !rest.iter().any(|c| c.contains(p)),
+                None => false,
+            },
+        }
+    }
+
+    pub fn bbox(&self) -> BBox {
+        match self {
+            Solid::Empty => BBox::empty(),
+            Solid::Prim { prim, to_world, .. } => {
+                let corners = prim.bbox().corners();
+                BBox::from_points(corners.iter().map(|&c| to_world.apply(c)))
+            }
+            Solid::Union(cs) => cs.iter().fold(BBox::empty(), |b, c| b.union(&c.bbox())),
+            Solid::Inter(cs) => match cs.split_first() {
+                Some((first, rest)) => rest.iter().fold(first.bbox(), |b, c| b.inter(&c.bbox())),
+                None => BBox::empty(),
+            },
+            Solid::Diff(cs) => cs.first().map_or(BBox::empty(), |c| c.bbox()),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::f64::consts;
 
 use log::*;
@@ -5,10 +6,14 @@ use log::*;
 use indexmap::{indexset, IndexMap};
 
 use crate::{
-    base::list_op::Permutation,
-    base::num::Num,
+    base::geom::{normalize_angle, Affine3, Point},
+    base::list_op::{Partitioning, Permutation},
+    base::num::{num, Num},
     cad::{Cad, EGraph, ListVar as LV, Vec3},
-    hyperparameters::SOLVE_ROUND,
+    constant::{add_form, closed_form, is_long},
+    hyperparameters::{
+        ABS_EPSILON, POLAR_LEAVE_ONE_OUT_MAX, REMOVE_MAX, SOLVE_MAX_DEGREE, SOLVE_ROUND,
+    },
 };
 
 use egg::Id;
@@ -17,6 +22,7 @@ fn f(u: usize) -> f64 {
     u as f64
 }
 
+#[allow(dead_code)]
 #[derive(Debug, PartialEq)]
 pub struct VecFormula {
     x: Formula,
@@ -28,6 +34,16 @@ pub struct VecFormula {
 enum Formula {
     Deg1(Deg1),
     Deg2(Deg2),
+    /// Coefficients of `i^0, i^1, ...`
+    Poly(Vec<Num>),
+    /// `a * r^i + b`
+    Geom {
+        a: Num,
+        r: Num,
+        b: Num,
+    },
+    /// The inner formula applied to `i % period`
+    Periodic(usize, Box<Formula>),
 }
 
 macro_rules! eadd {
@@ -38,99 +54,427 @@ macro_rules! eadd {
 }
 
 impl Formula {
+    fn eval(&self, i: f64) -> f64 {
+        match self {
+            Formula::Deg1(f) => f.a.to_f64() * i + f.b.to_f64(),
+            Formula::Deg2(f) => f.a.to_f64() * i * i + f.b.to_f64() * i + f.c.to_f64(),
+            Formula::Poly(cs) => cs.iter().rev().fold(0.0, |acc, c| acc * i + c.to_f64()),
+            Formula::Geom { a, r, b } => a.to_f64() * r.to_f64().powf(i) + b.to_f64(),
+            Formula::Periodic(p, inner) => inner.eval(i % f(*p)),
+        }
+    }
+
     fn add_to_egraph(&self, e: &mut EGraph, i: Id) -> Id {
         use Cad::*;
         match self {
             Formula::Deg1(f) => {
-                let a = eadd!(e, Num(f.a.into()));
-                let b = eadd!(e, Num(f.b.into()));
+                let a = eadd!(e, Num(f.a.clone()));
+                let b = eadd!(e, Num(f.b.clone()));
                 let mul = eadd!(e, Mul, a, i);
                 eadd!(e, Add, mul, b)
             }
             Formula::Deg2(f) => {
-                let a = eadd!(e, Num(f.a.into()));
-                let b = eadd!(e, Num(f.b.into()));
-                let c = eadd!(e, Num(f.c.into()));
+                let a = eadd!(e, Num(f.a.clone()));
+                let b = eadd!(e, Num(f.b.clone()));
+                let c = eadd!(e, Num(f.c.clone()));
                 let ii = eadd!(e, Mul, i, i);
                 let a2 = eadd!(e, Mul, a, ii);
                 let b1 = eadd!(e, Mul, b, i);
                 let ab = eadd!(e, Add, a2, b1);
                 eadd!(e, Add, ab, c)
             }
+            Formula::Poly(cs) => {
+                // Horner's scheme, from the highest power down
+                let (last, rest) = cs.split_last().unwrap();
+                let mut acc = eadd!(e, Num(last.clone()));
+                for c in rest.iter().rev() {
+                    let c = eadd!(e, Num(c.clone()));
+                    let mul = eadd!(e, Mul, acc, i);
+                    acc = eadd!(e, Add, mul, c);
+                }
+                acc
+            }
+            Formula::Geom { a, r, b } => {
+                let a = eadd!(e, Num(a.clone()));
+                let r = eadd!(e, Num(r.clone()));
+                let b = eadd!(e, Num(b.clone()));
+                let pow = eadd!(e, Pow, r, i);
+                let mul = eadd!(e, Mul, a, pow);
+                eadd!(e, Add, mul, b)
+            }
+            Formula::Periodic(p, inner) => {
+                let p = eadd!(e, Num((*p).into()));
+                let m = eadd!(e, Mod, i, p);
+                inner.add_to_egraph(e, m)
+            }
         }
     }
 }
 
 #[derive(Debug, PartialEq)]
 struct Deg1 {
-    a: f64,
-    b: f64,
+    a: Num,
+    b: Num,
 }
 
 #[derive(Debug, PartialEq)]
 struct Deg2 {
-    a: f64,
-    b: f64,
-    c: f64,
+    a: Num,
+    b: Num,
+    c: Num,
+}
+
+// SZ_SOLVE_TOLERANCE: how far, in model units, a point may be from a fitted formula.
+// Noisy models need a looser value, like 0.01.
+sz_param!(SOLVE_TOLERANCE: f64 = crate::hyperparameters::SOLVE_TOLERANCE);
+
+// per thread, so a test can loosen it without the environment
+thread_local! {
+    static TOLERANCE: Cell<f64> = Cell::new(*SOLVE_TOLERANCE);
+}
+
+// Whether a point is close enough to a fitted formula. Equal values are `same`.
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() <= TOLERANCE.with(Cell::get) || Num::from(a).is_close(b)
+}
+
+fn round(v: f64) -> f64 {
+    (v / SOLVE_ROUND).round() * SOLVE_ROUND
+}
+
+thread_local! {
+    static MAX_DEVIATION: Cell<f64> = const { Cell::new(0.0) };
+}
+
+/// The largest deviation of the data from a formula the solver added on this thread,
+/// since the last call.
+pub fn take_max_deviation() -> f64 {
+    MAX_DEVIATION.with(|max| max.replace(0.0))
+}
+
+// only for formulas that go into the e-graph, not for fits that are tried and dropped
+fn record_deviation(dev: f64) {
+    MAX_DEVIATION.with(|max| max.set(max.get().max(dev)));
 }
 
+fn max_deviation(vs: &[Num], fun: &Formula) -> f64 {
+    vs.iter()
+        .enumerate()
+        .map(|(i, v)| (v.to_f64() - fun.eval(f(i))).abs())
+        .fold(0.0, f64::max)
+}
+
+// The largest distance between the data and a formula, if every point is within tolerance.
+fn deviation(vs: &[Num], formula: impl Fn(f64) -> f64) -> Option<f64> {
+    vs.iter().enumerate().try_fold(0.0f64, |max, (i, v)| {
+        let (v, fv) = (v.to_f64(), formula(f(i)));
+        close(v, fv).then(|| max.max((v - fv).abs()))
+    })
+}
+
+// Of a least-squares fit and its rounding, the rounding if it is within tolerance and adds
+// no more error than the noise the fit leaves, else the fit if it is within tolerance.
+fn prefer_round<C>(fit: C, rounded: C, deviation: impl Fn(&C) -> Option<f64>) -> Option<(C, f64)> {
+    let fit_dev = deviation(&fit);
+    let limit = fit_dev.map_or(f64::INFINITY, |dev| (2.0 * dev).max(ABS_EPSILON));
+    match deviation(&rounded) {
+        Some(dev) if dev <= limit => Some((rounded, dev)),
+        _ => fit_dev.map(|dev| (fit, dev)),
+    }
+}
+
+// least squares a * i + b
 fn solve_deg1(vs: &[Num]) -> Option<Deg1> {
-    let i1 = 0.0;
-    let i2 = 1.0;
-    let o1 = vs[0].to_f64();
-    let o2 = vs[1].to_f64();
-    let b = (o1 * i2) - (o2 * i1) / (i2 - i1);
-    let a = (o2 - b) / i2;
-
-    let rnd = SOLVE_ROUND;
-    let aa = (a / rnd).round() * rnd;
-    let bb = (b / rnd).round() * rnd;
-
-    let close = |a, b| {
-        vs.iter()
-            .enumerate()
-            .all(|(i, &v)| v.is_close(a * f(i) + b))
-    };
+    #[cfg(feature = "exact")]
+    if let Some(mut cs) = solve_poly_exact(vs, 1) {
+        let (a, b) = (cs.pop()?, cs.pop()?);
+        return Some(Deg1 { a, b });
+    }
 
-    if close(aa, bb) {
-        Some(Deg1 { a: aa, b: bb })
-    } else if close(a, b) {
-        Some(Deg1 { a, b })
-    } else {
-        None
+    let n = f(vs.len());
+    let mean_i = (n - 1.0) / 2.0;
+    let mean_v = vs.iter().map(|v| v.to_f64()).sum::<f64>() / n;
+    let (mut sxy, mut sxx) = (0.0, 0.0);
+    for (i, v) in vs.iter().enumerate() {
+        let di = f(i) - mean_i;
+        sxy += di * (v.to_f64() - mean_v);
+        sxx += di * di;
     }
+    let a = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+    let b = mean_v - a * mean_i;
+
+    let line = |&(a, b): &(f64, f64)| deviation(vs, |i| a * i + b);
+    let ((a, b), dev) = prefer_round((a, b), (round(a), round(b)), line)?;
+    debug!("Fit {} * i + {} to {:?}, max deviation {}", a, b, vs, dev);
+    Some(Deg1 {
+        a: a.into(),
+        b: b.into(),
+    })
 }
 
+// least squares a * i^2 + b * i + c
 fn solve_deg2(vs: &[Num]) -> Option<Deg2> {
-    let i1 = 0.0;
-    let i2 = 1.0;
-    let i3 = 2.0;
-    let o1 = vs[0].to_f64();
-    let o2 = vs[1].to_f64();
-    let o3 = vs[2].to_f64();
-    let a = (((o2 * i3) - (o3 * i2)) - ((i2 - i3) * (((o1 * i2) - (o2 * i1)) / (i1 - i2))))
-        / ((i2 - i3) * ((i2 * i3) - (i1 * i2)));
-    let c = (a * i1 * i2) - (((o1 * i2) - (o2 * i1)) / (i1 - i2));
-    let b = (o3 - c - (a * i3 * i3)) / i3;
-    let mut ivs = vs.iter().enumerate();
-
-    let works = ivs.all(|(i, &v)| v.is_close(a * f(i * i) + b * f(i) + c));
-
-    if works {
-        Some(Deg2 { a, b, c })
-    } else {
-        None
+    let mut cs = solve_poly(vs, 2)?;
+    let (a, b, c) = (cs.pop()?, cs.pop()?, cs.pop()?);
+    Some(Deg2 { a, b, c })
+}
+
+// least squares polynomial of the given degree, coefficients of i^0 first
+fn solve_poly(vs: &[Num], degree: usize) -> Option<Vec<Num>> {
+    #[cfg(feature = "exact")]
+    if let Some(cs) = solve_poly_exact(vs, degree) {
+        return Some(cs);
+    }
+
+    let n = degree + 1;
+    let mut m = vec![vec![0.0; n]; n];
+    let mut rhs = vec![0.0; n];
+    for (i, v) in vs.iter().enumerate() {
+        let row: Vec<f64> = (0..n).map(|k| f(i).powi(k as i32)).collect();
+        for j in 0..n {
+            for k in 0..n {
+                m[j][k] += row[j] * row[k];
+            }
+            rhs[j] += row[j] * v.to_f64();
+        }
     }
+    let cs = solve_linear(m, rhs)?;
+    let eval = |cs: &[f64], i: f64| cs.iter().rev().fold(0.0, |acc, c| acc * i + c);
+
+    let rounded: Vec<f64> = cs.iter().map(|&c| round(c)).collect();
+    let (cs, dev) = prefer_round(cs, rounded, |cs| deviation(vs, |i| eval(cs, i)))?;
+    debug!("Fit polynomial {:?} to {:?}, max deviation {}", cs, vs, dev);
+    Some(cs.into_iter().map(Num::from).collect())
 }
 
-fn solve_list_fn(xs: &[Num]) -> Option<Formula> {
+// The polynomial through every value, solved in rationals, so 1/3 stays 1/3.
+#[cfg(feature = "exact")]
+fn solve_poly_exact(vs: &[Num], degree: usize) -> Option<Vec<Num>> {
+    let n = degree + 1;
+    let zero = Num::from(0);
+    let mut m = vec![vec![zero.clone(); n]; n];
+    let mut rhs = vec![zero.clone(); n];
+    for (i, v) in vs.iter().enumerate() {
+        let row: Vec<Num> = (0..n).map(|k| Num::from(i.pow(k as u32))).collect();
+        for j in 0..n {
+            for k in 0..n {
+                m[j][k] = &m[j][k] + &(&row[j] * &row[k]);
+            }
+            rhs[j] = &rhs[j] + &(&row[j] * v);
+        }
+    }
+
+    // no rounding, so any nonzero pivot will do
+    for col in 0..n {
+        let pivot = (col..n).find(|&row| m[row][col] != zero)?;
+        m.swap(col, pivot);
+        rhs.swap(col, pivot);
+        let pivot_row = m[col].clone();
+        for row in col + 1..n {
+            let k = m[row][col].checked_div(&pivot_row[col])?;
+            for (x, p) in m[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *x = &*x - &(&k * p);
+            }
+            rhs[row] = &rhs[row] - &(&k * &rhs[col]);
+        }
+    }
+    let mut cs = vec![zero.clone(); n];
+    for row in (0..n).rev() {
+        let s = (row + 1..n).fold(zero.clone(), |acc, c| acc + &m[row][c] * &cs[c]);
+        cs[row] = (&rhs[row] - &s).checked_div(&m[row][row])?;
+    }
+
+    let eval = |i: usize| {
+        cs.iter()
+            .rev()
+            .fold(zero.clone(), |acc, c| acc * Num::from(i) + c.clone())
+    };
+    vs.iter()
+        .enumerate()
+        .all(|(i, v)| eval(i) == *v)
+        .then_some(cs)
+}
+
+// a * r^i + b, for r away from 0 and 1
+fn solve_geom(vs: &[Num]) -> Option<Formula> {
+    #[cfg(feature = "exact")]
+    if let Some(geom) = solve_geom_exact(vs) {
+        return Some(geom);
+    }
+
+    // the differences d_i = a * (r - 1) * r^i satisfy d_{i+1} = r * d_i
+    let ds: Vec<f64> = vs
+        .windows(2)
+        .map(|w| w[1].to_f64() - w[0].to_f64())
+        .collect();
+    let (mut num, mut den) = (0.0, 0.0);
+    for w in ds.windows(2) {
+        num += w[0] * w[1];
+        den += w[0] * w[0];
+    }
+    if den == 0.0 {
+        return None;
+    }
+    let r = num / den;
+
+    for &r in &[round(r), r] {
+        if r.abs() < SOLVE_ROUND || Num::from(r).is_close(1) {
+            continue;
+        }
+        // least squares for a and b given r
+        let n = f(vs.len());
+        let (mut sp, mut spp, mut sv, mut spv) = (0.0, 0.0, 0.0, 0.0);
+        for (i, v) in vs.iter().enumerate() {
+            let p = r.powi(i as i32);
+            sp += p;
+            spp += p * p;
+            sv += v.to_f64();
+            spv += p * v.to_f64();
+        }
+        let sol = solve_linear(vec![vec![spp, sp], vec![sp, n]], vec![spv, sv])?;
+        let (fit, rounded) = ((sol[0], sol[1]), (round(sol[0]), round(sol[1])));
+        let geom = |&(a, b): &(f64, f64)| deviation(vs, |i| a * r.powf(i) + b);
+        if let Some(((a, b), dev)) = prefer_round(fit, rounded, geom) {
+            debug!(
+                "Fit {} * {}^i + {} to {:?}, max deviation {}",
+                a, r, b, vs, dev
+            );
+            return Some(Formula::Geom {
+                a: a.into(),
+                r: r.into(),
+                b: b.into(),
+            });
+        }
+    }
+    None
+}
+
+// a * r^i + b through every value, solved in rationals, so a ratio of 1/3 stays 1/3.
+#[cfg(feature = "exact")]
+fn solve_geom_exact(vs: &[Num]) -> Option<Formula> {
+    let ds: Vec<Num> = vs.windows(2).map(|w| &w[1] - &w[0]).collect();
+    let r = ds.get(1)?.checked_div(ds.first()?)?;
+    if r.to_f64().abs() < SOLVE_ROUND || r == num(1) {
+        return None;
+    }
+    // d_0 = a * (r - 1)
+    let a = ds[0].checked_div(&(&r - &num(1)))?;
+    let b = &vs[0] - &a;
+    let mut power = num(1);
+    for v in vs {
+        if &(&a * &power) + &b != *v {
+            return None;
+        }
+        power = &power * &r;
+    }
+    Some(Formula::Geom { a, r, b })
+}
+
+// a repeating pattern whose first period has a formula of its own
+fn solve_periodic(vs: &[Num]) -> Option<Formula> {
+    for p in 2..=vs.len() / 2 {
+        if vs
+            .iter()
+            .enumerate()
+            .skip(p)
+            .all(|(i, v)| same(v, &vs[i % p]))
+        {
+            if let Some(inner) = solve_aperiodic(&vs[..p]) {
+                return Some(Formula::Periodic(p, Box::new(inner)));
+            }
+        }
+    }
+    None
+}
+
+// Each formula but the line must be overdetermined by the list, so that fitting it means
+// something. A line is tried on any list, since two elements make a loop too.
+fn solve_aperiodic(xs: &[Num]) -> Option<Formula> {
     if let Some(sol1) = solve_deg1(xs) {
         return Some(Formula::Deg1(sol1));
     }
     if xs.len() > 3 {
-        return solve_deg2(xs).map(Formula::Deg2);
+        if let Some(sol2) = solve_deg2(xs) {
+            return Some(Formula::Deg2(sol2));
+        }
+        if let Some(geom) = solve_geom(xs) {
+            return Some(geom);
+        }
     }
-    None
+    (3..=SOLVE_MAX_DEGREE)
+        .take_while(|degree| xs.len() > degree + 1)
+        .find_map(|degree| solve_poly(xs, degree).map(Formula::Poly))
+}
+
+fn solve_list_fn(xs: &[Num]) -> Option<Formula> {
+    solve_aperiodic(xs).or_else(|| solve_periodic(xs))
+}
+
+// The loop range (start, step) that leaves the simplest formulas. Looping over
+// v = start + step * i, each a * i + b becomes a / step * v + (b - a / step * start).
+// Each formula is tried as the loop variable itself, and with only its offset removed,
+// counting down for a negative slope. As in the cost, each offset and slope is a number
+// and a range adds two, so a range is only kept if it removes more than two numbers.
+fn choose_range(funs: &mut [(usize, Formula)]) -> (Num, Num) {
+    let deg1s: Option<Vec<&Deg1>> = funs
+        .iter()
+        .map(|(_, fun)| match fun {
+            Formula::Deg1(d) => Some(d),
+            _ => None,
+        })
+        .collect();
+    let deg1s = match deg1s {
+        Some(deg1s) => deg1s,
+        None => return (num(0), num(1)),
+    };
+
+    // the candidate steps are not zero
+    let shift = |d: &Deg1, (start, step): &(Num, Num)| {
+        let a = d.a.checked_div(step).unwrap();
+        let b = &d.b - &(&a * start);
+        Deg1 {
+            a,
+            b: if b.is_close(0) { num(0) } else { b },
+        }
+    };
+    let (zero, one) = (num(0), num(1));
+    let score = |range: &(Num, Num)| {
+        let formulas = deg1s
+            .iter()
+            .map(|d| {
+                let d = shift(d, range);
+                usize::from(d.b != zero) + usize::from(d.a != zero && d.a != one)
+            })
+            .sum::<usize>();
+        formulas + 2 * usize::from(*range != (num(0), num(1)))
+    };
+
+    let mut candidates = vec![];
+    for d in &deg1s {
+        if d.a.is_close(0) {
+            continue;
+        }
+        candidates.push((d.b.clone(), d.a.clone()));
+        let (a, b) = (d.a.to_f64(), d.b.to_f64());
+        let start = b / a.abs();
+        if Num::from(start).is_close(start.round()) {
+            candidates.push((start.round().into(), a.signum().into()));
+        }
+    }
+
+    let mut best = (num(0), num(1));
+    for range in candidates {
+        if score(&range) < score(&best) {
+            best = range;
+        }
+    }
+
+    for (_, fun) in funs.iter_mut() {
+        if let Formula::Deg1(d) = fun {
+            *d = shift(d, &best);
+        }
+    }
+    best
 }
 
 fn solve_and_add(egraph: &mut EGraph, xs: &[Num], ys: &[Num], zs: &[Num]) -> Option<Id> {
@@ -156,14 +500,22 @@ fn solve_and_add(egraph: &mut EGraph, xs: &[Num], ys: &[Num], zs: &[Num]) -> Opt
         Cad::ListVar(LV("k")),
     ];
     let mut inserted = [None; 3];
+    let mut ranges = vec![];
+    let mut dev = 0.0f64;
 
     for (((&chunk_len, lists), inner), var) in by_chunk.iter().zip(&inners).zip(vars) {
+        let mut funs = vec![];
         for (index, list) in lists {
             let slice = &list[..chunk_len];
             let nums = unrun(slice, *inner)?;
             let fun = solve_list_fn(&nums)?;
+            dev = dev.max(max_deviation(&nums, &fun));
+            funs.push((*index, fun));
+        }
+        ranges.push(choose_range(&mut funs));
+        for (index, fun) in funs {
             let var_id = egraph.add(var.clone());
-            inserted[*index] = Some(fun.add_to_egraph(egraph, var_id));
+            inserted[index] = Some(fun.add_to_egraph(egraph, var_id));
         }
     }
 
@@ -171,11 +523,19 @@ fn solve_and_add(egraph: &mut EGraph, xs: &[Num], ys: &[Num], zs: &[Num]) -> Opt
     let mut children: Vec<_> = by_chunk
         .keys()
         .zip(&inners)
-        .map(|(n, inner)| {
+        .zip(ranges)
+        .map(|((n, inner), (start, step))| {
             assert_eq!(n % inner, 0);
             let len = n / inner;
             lens.push(len);
-            egraph.add(Cad::Num(len.into()))
+            let len = egraph.add(Cad::Num(len.into()));
+            if (&start, &step) == (&num(0), &num(1)) {
+                len
+            } else {
+                let start = egraph.add(Cad::Num(start));
+                let step = egraph.add(Cad::Num(step));
+                egraph.add(Cad::Range([start, step, len]))
+            }
         })
         .collect();
     assert_eq!(lens.iter().product::<usize>(), xs.len());
@@ -185,27 +545,205 @@ fn solve_and_add(egraph: &mut EGraph, xs: &[Num], ys: &[Num], zs: &[Num]) -> Opt
     let vec = egraph.add(Cad::Vec3([x, y, z]));
     children.push(vec);
     let map = egraph.add(Cad::MapI(children));
+    record_deviation(dev);
     Some(map)
 }
 
+// Rows of points, split where the coordinate `key` changes. The row lengths may depend on
+// the row, as in a triangular stack or a staggered honeycomb, giving
+// (MapI rows (len i) (Vec3 (+ (start_x i) (* step_x j)) ...)).
+fn solve_rows(egraph: &mut EGraph, xs: &[Num], ys: &[Num], zs: &[Num]) -> Option<Id> {
+    let coords = [xs, ys, zs];
+    (0..3).find_map(|key| solve_rows_by(egraph, &coords, key))
+}
+
+fn solve_rows_by(egraph: &mut EGraph, coords: &[&[Num]; 3], key: usize) -> Option<Id> {
+    let mut lens = vec![1];
+    for w in coords[key].windows(2) {
+        if same(&w[0], &w[1]) {
+            *lens.last_mut().unwrap() += 1;
+        } else {
+            lens.push(1);
+        }
+    }
+    if lens.len() < 3 || lens.iter().all(|&l| l == 1) {
+        return None;
+    }
+
+    let len_nums: Vec<Num> = lens.iter().map(|&l| l.into()).collect();
+    let len_fn = solve_list_fn(&len_nums)?;
+    if (0..lens.len()).any(|i| len_fn.eval(f(i)).round() as usize != lens[i]) {
+        return None;
+    }
+
+    let starts: Vec<usize> = lens
+        .iter()
+        .scan(0, |start, &l| {
+            *start += l;
+            Some(*start - l)
+        })
+        .collect();
+
+    // each coordinate is a formula of the row, plus a step along the row
+    let mut fits = vec![];
+    let mut dev = 0.0f64;
+    for vs in coords {
+        let rows: Vec<&[Num]> = starts
+            .iter()
+            .zip(&lens)
+            .map(|(&s, &l)| &vs[s..s + l])
+            .collect();
+        let first: Vec<Num> = rows.iter().map(|row| row[0].clone()).collect();
+        let start_fn = solve_list_fn(&first)?;
+        dev = dev.max(max_deviation(&first, &start_fn));
+        let steps = rows
+            .iter()
+            .filter(|row| row.len() > 1)
+            .map(|row| solve_deg1(row).map(|d| d.a.to_f64()))
+            .collect::<Option<Vec<f64>>>()?;
+        let step = steps.iter().sum::<f64>() / f(steps.len());
+        let along = |&step: &f64| {
+            rows.iter().enumerate().try_fold(0.0f64, |max, (i, row)| {
+                let start = start_fn.eval(f(i));
+                deviation(row, |j| start + step * j).map(|dev| max.max(dev))
+            })
+        };
+        let (step, step_dev) = prefer_round(step, round(step), along)?;
+        dev = dev.max(step_dev);
+        fits.push((start_fn, step));
+    }
+
+    let i = egraph.add(Cad::ListVar(LV("i")));
+    let j = egraph.add(Cad::ListVar(LV("j")));
+    let mut vec = [i; 3];
+    for (v, (start_fn, step)) in vec.iter_mut().zip(fits) {
+        let start = start_fn.add_to_egraph(egraph, i);
+        let step = egraph.add(Cad::Num(step.into()));
+        let along = egraph.add(Cad::Mul([step, j]));
+        *v = egraph.add(Cad::Add([start, along]));
+    }
+    let n = egraph.add(Cad::Num(lens.len().into()));
+    let len = len_fn.add_to_egraph(egraph, i);
+    let vec = egraph.add(Cad::Vec3(vec));
+    debug!("Solved rows of lengths {:?}", lens);
+    record_deviation(dev);
+    Some(egraph.add(Cad::MapI(vec![n, len, vec])))
+}
+
+// Points on a lattice with arbitrary basis vectors, row by row, like a rhombic grid
+// of holes or a hexagonal packing:
+// (MapI rows cols (Vec3 (+ o_x (+ (* a_x i) (* b_x j))) ...)).
+// Every other row may be shifted by c, like the courses of a brick bond, which adds
+// (* c_x (% i 2)).
+fn solve_lattice(egraph: &mut EGraph, xs: &[Num], ys: &[Num], zs: &[Num]) -> Option<Id> {
+    let points: Vec<Point> = (0..xs.len())
+        .map(|k| (xs[k].to_f64(), ys[k].to_f64(), zs[k].to_f64()))
+        .collect();
+    let n = points.len();
+    if n < 4 {
+        return None;
+    }
+    let same_point = |p: Point, q: Point| {
+        [p.0 - q.0, p.1 - q.1, p.2 - q.2]
+            .iter()
+            .all(|d| d.abs() <= ABS_EPSILON)
+    };
+    let step =
+        |o: Point, v: Point, t: usize| (o.0 + v.0 * f(t), o.1 + v.1 * f(t), o.2 + v.2 * f(t));
+    let zero = (0.0, 0.0, 0.0);
+
+    // b runs along a row, a from one row to the next, and c shifts the odd rows
+    let o = points[0];
+    let b = sub(points[1], o);
+    let cols = (2..n)
+        .find(|&k| !same_point(points[k], step(o, b, k)))
+        .filter(|&cols| n % cols == 0 && n / cols > 1)?;
+    let rows = n / cols;
+    let a = match rows {
+        2 => sub(points[cols], o),
+        _ => {
+            let (x, y, z) = sub(points[2 * cols], o);
+            (x / 2.0, y / 2.0, z / 2.0)
+        }
+    };
+    let c = match sub(sub(points[cols], o), a) {
+        c if same_point(c, zero) => zero,
+        c => c,
+    };
+    if same_point(a, zero) || same_point(b, zero) {
+        return None;
+    }
+
+    let round_point = |p: Point| (round(p.0), round(p.1), round(p.2));
+    let basis = [o, a, b, c];
+    let [o, a, b, c] = [basis.map(round_point), basis]
+        .iter()
+        .copied()
+        .find(|&[o, a, b, c]| {
+            points.iter().enumerate().all(|(k, &p)| {
+                let (row, col) = (k / cols, k % cols);
+                same_point(p, step(step(step(o, a, row), c, row % 2), b, col))
+            })
+        })?;
+
+    let i = egraph.add(Cad::ListVar(LV("i")));
+    let j = egraph.add(Cad::ListVar(LV("j")));
+    let parity = (c != zero).then(|| {
+        let two = egraph.add(Cad::Num(num(2)));
+        egraph.add(Cad::Mod([i, two]))
+    });
+    let mut lin = |o: f64, a: f64, b: f64, c: f64| {
+        let mut sum = None;
+        for (c, var) in [(c, parity), (b, Some(j)), (a, Some(i))] {
+            let var = match var {
+                Some(var) if c != 0.0 => var,
+                _ => continue,
+            };
+            let c = add_num(egraph, c.into());
+            let term = egraph.add(Cad::Mul([c, var]));
+            sum = Some(match sum {
+                Some(s) => egraph.add(Cad::Add([term, s])),
+                None => term,
+            });
+        }
+        match sum {
+            Some(s) if o == 0.0 => s,
+            Some(s) => {
+                let o = add_num(egraph, o.into());
+                egraph.add(Cad::Add([o, s]))
+            }
+            None => add_num(egraph, o.into()),
+        }
+    };
+    let x = lin(o.0, a.0, b.0, c.0);
+    let y = lin(o.1, a.1, b.1, c.1);
+    let z = lin(o.2, a.2, b.2, c.2);
+    let vec = egraph.add(Cad::Vec3([x, y, z]));
+    let rows = egraph.add(Cad::Num(rows.into()));
+    let cols_id = egraph.add(Cad::Num(cols.into()));
+    debug!(
+        "Solved a lattice with basis {:?} {:?}, odd rows shifted by {:?}",
+        a, b, c
+    );
+    Some(egraph.add(Cad::MapI(vec![rows, cols_id, vec])))
+}
+
 fn solve_vec(egraph: &mut EGraph, list: &[Vec3]) -> Vec<Id> {
-    if list.iter().all(|&v| v == list[0]) {
+    if list.iter().all(|v| *v == list[0]) {
         // don't infer here, it'll become a repeat
         return vec![];
     }
 
-    let xs: Vec<Num> = list.iter().map(|v| v.0).collect();
-    let ys: Vec<Num> = list.iter().map(|v| v.1).collect();
-    let zs: Vec<Num> = list.iter().map(|v| v.2).collect();
-
-    let xys: Vec<(Num, Num)> = list.iter().map(|v| (v.0, v.1)).collect();
-    let yxs: Vec<(Num, Num)> = list.iter().map(|v| (v.1, v.0)).collect();
+    let xs: Vec<Num> = list.iter().map(|v| v.0.clone()).collect();
+    let ys: Vec<Num> = list.iter().map(|v| v.1.clone()).collect();
+    let zs: Vec<Num> = list.iter().map(|v| v.2.clone()).collect();
 
-    let yzs: Vec<(Num, Num)> = list.iter().map(|v| (v.1, v.2)).collect();
-    let zys: Vec<(Num, Num)> = list.iter().map(|v| (v.2, v.1)).collect();
-
-    let xzs: Vec<(Num, Num)> = list.iter().map(|v| (v.0, v.2)).collect();
-    let zxs: Vec<(Num, Num)> = list.iter().map(|v| (v.2, v.0)).collect();
+    let pairs = |a: &[Num], b: &[Num]| -> Vec<(Num, Num)> {
+        a.iter().cloned().zip(b.iter().cloned()).collect()
+    };
+    let (xys, yxs) = (pairs(&xs, &ys), pairs(&ys, &xs));
+    let (yzs, zys) = (pairs(&ys, &zs), pairs(&zs, &ys));
+    let (xzs, zxs) = (pairs(&xs, &zs), pairs(&zs, &xs));
 
     let len = xs.len();
     assert_eq!(len, ys.len());
@@ -213,7 +751,10 @@ fn solve_vec(egraph: &mut EGraph, list: &[Vec3]) -> Vec<Id> {
 
     let mut results = vec![];
 
-    results.extend(solve_and_add(egraph, &xs, &ys, &zs));
+    let solved = solve_and_add(egraph, &xs, &ys, &zs)
+        .or_else(|| solve_rows(egraph, &xs, &ys, &zs))
+        .or_else(|| solve_lattice(egraph, &xs, &ys, &zs));
+    results.extend(solved);
 
     let perms = indexset![
         Permutation::sort(&xs),
@@ -234,17 +775,79 @@ fn solve_vec(egraph: &mut EGraph, list: &[Vec3]) -> Vec<Id> {
         let xs = perm.apply(&xs);
         let ys = perm.apply(&ys);
         let zs = perm.apply(&zs);
-        if let Some(added_mapi) = solve_and_add(egraph, &xs, &ys, &zs) {
+        let solved = solve_and_add(egraph, &xs, &ys, &zs)
+            .or_else(|| solve_rows(egraph, &xs, &ys, &zs))
+            .or_else(|| solve_lattice(egraph, &xs, &ys, &zs));
+        if let Some(added_mapi) = solved {
             let p = Cad::Permutation(perm.clone());
             let e = Cad::Unsort([egraph.add(p), added_mapi]);
             results.push(egraph.add(e));
         }
     }
 
+    if results.is_empty() {
+        results.extend(solve_missing(egraph, list));
+    }
     results
 }
 
-fn polar_one(center: (f64, f64, f64), v: Vec3) -> Vec3 {
+// A full grid with a few elements missing, as (Remove grid indices).
+fn solve_missing(egraph: &mut EGraph, list: &[Vec3]) -> Option<Id> {
+    let axis = |get: fn(&Vec3) -> &Num| {
+        let mut vals: Vec<Num> = list.iter().map(get).cloned().collect();
+        vals.sort();
+        vals.dedup_by(|a, b| same(a, b));
+        vals
+    };
+    let (us, vs, ws) = (axis(|v| &v.0), axis(|v| &v.1), axis(|v| &v.2));
+    let full = us.len() * vs.len() * ws.len();
+    let missing = full.checked_sub(list.len())?;
+    if missing == 0 || missing > REMOVE_MAX.min(full / 4) {
+        return None;
+    }
+
+    // the index of each element in the full grid, x-major
+    let position = |vals: &[Num], x: &Num| vals.iter().position(|v| same(v, x));
+    let keys = list
+        .iter()
+        .map(|v| {
+            let a = position(&us, &v.0)?;
+            let b = position(&vs, &v.1)?;
+            let c = position(&ws, &v.2)?;
+            Some((a * vs.len() + b) * ws.len() + c)
+        })
+        .collect::<Option<Vec<usize>>>()?;
+    let mut present = vec![false; full];
+    for &k in &keys {
+        if std::mem::replace(&mut present[k], true) {
+            return None;
+        }
+    }
+
+    let (nv, nw) = (vs.len(), ws.len());
+    let xs: Vec<Num> = (0..full).map(|k| us[k / (nv * nw)].clone()).collect();
+    let ys: Vec<Num> = (0..full).map(|k| vs[k / nw % nv].clone()).collect();
+    let zs: Vec<Num> = (0..full).map(|k| ws[k % nw].clone()).collect();
+    let mapi = solve_and_add(egraph, &xs, &ys, &zs)?;
+
+    let indices = (0..full)
+        .filter(|&k| !present[k])
+        .map(|k| add_num(egraph, k.into()))
+        .collect();
+    let indices = egraph.add(Cad::List(indices));
+    let removed = egraph.add(Cad::Remove([mapi, indices]));
+    debug!("Solved a grid of {} missing {}", full, missing);
+
+    let perm = Permutation::sort(&keys);
+    if perm.is_ordered() {
+        Some(removed)
+    } else {
+        let p = egraph.add(Cad::Permutation(perm));
+        Some(egraph.add(Cad::Unsort([p, removed])))
+    }
+}
+
+fn polar_one(center: Point, v: &Vec3) -> (f64, f64, f64) {
     let (x, y, z) = (v.0.to_f64(), v.1.to_f64(), v.2.to_f64());
     let (a, b, c) = center;
     let (xa, yb, zc) = (x - a, y - b, z - c);
@@ -255,55 +858,519 @@ fn polar_one(center: (f64, f64, f64), v: Vec3) -> Vec3 {
     } else {
         (zc / r).acos() * 180.0 / consts::PI
     };
-    (r.into(), theta.into(), phi.into())
+    (r, theta, phi)
 }
 
-fn polarize(list: &[Vec3]) -> (Vec3, Vec<Vec3>) {
-    let xc = list.iter().map(|v| v.0.to_f64()).sum::<f64>();
-    let yc = list.iter().map(|v| v.1.to_f64()).sum::<f64>();
-    let zc = list.iter().map(|v| v.2.to_f64()).sum::<f64>();
-    let n = f(list.len());
-    let center = (xc / n, yc / n, zc / n);
-    let new_list = list.iter().map(|&v| polar_one(center, v)).collect();
-    let num_center = (center.0.into(), center.1.into(), center.2.into());
-    (num_center, new_list)
+// Spherical coordinates about `center`, with theta unwrapped so that arcs
+// crossing the negative x axis stay linear.
+fn polarize(list: &[Vec3], center: Point) -> Vec<Vec3> {
+    let mut prev: Option<f64> = None;
+    list.iter()
+        .map(|v| {
+            let (r, raw, phi) = polar_one(center, v);
+            let theta = match prev {
+                Some(prev) => prev + normalize_angle(raw - prev),
+                None => raw,
+            };
+            prev = Some(theta);
+            (r.into(), theta.into(), phi.into())
+        })
+        .collect()
 }
 
-fn add_num(egraph: &mut EGraph, n: Num) -> Id {
-    static NS: &[f64] = &[consts::SQRT_2, 0.0, 90.0, 180.0, 270.0, 360.0];
+fn centroid(points: &[Point]) -> Point {
+    let n = f(points.len());
+    let sum = points
+        .iter()
+        .fold((0.0, 0.0, 0.0), |s, p| (s.0 + p.0, s.1 + p.1, s.2 + p.2));
+    (sum.0 / n, sum.1 / n, sum.2 / n)
+}
+
+// Gaussian elimination with partial pivoting.
+fn solve_linear(mut m: Vec<Vec<f64>>, mut rhs: Vec<f64>) -> Option<Vec<f64>> {
+    let n = rhs.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| m[i][col].abs().total_cmp(&m[j][col].abs()))?;
+        if m[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        m.swap(col, pivot);
+        rhs.swap(col, pivot);
+        let pivot_row = m[col].clone();
+        for row in col + 1..n {
+            let k = m[row][col] / pivot_row[col];
+            for (x, p) in m[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *x -= k * p;
+            }
+            rhs[row] -= k * rhs[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let s: f64 = (row + 1..n).map(|c| m[row][c] * x[c]).sum();
+        x[row] = (rhs[row] - s) / m[row][row];
+    }
+    x.iter().all(|v| v.is_finite()).then_some(x)
+}
+
+// Kasa's algebraic fit in any dimension: minimise the sum of (|p|^2 + d.p + e)^2
+// over d and e; the centre is -d/2.
+fn fit_center(points: &[Vec<f64>]) -> Option<Vec<f64>> {
+    let dim = points.first()?.len();
+    let mut m = vec![vec![0.0; dim + 1]; dim + 1];
+    let mut rhs = vec![0.0; dim + 1];
+    for p in points {
+        let mut row = p.clone();
+        row.push(1.0);
+        let w = -p.iter().map(|x| x * x).sum::<f64>();
+        for i in 0..=dim {
+            for j in 0..=dim {
+                m[i][j] += row[i] * row[j];
+            }
+            rhs[i] += row[i] * w;
+        }
+    }
+    let sol = solve_linear(m, rhs)?;
+    Some(sol[..dim].iter().map(|d| -d / 2.0).collect())
+}
+
+// Centre of the least-squares circle through 2d points.
+fn fit_circle(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let points: Vec<_> = points.iter().map(|p| vec![p.0, p.1]).collect();
+    let c = fit_center(&points)?;
+    Some((c[0], c[1]))
+}
+
+fn sub(a: Point, b: Point) -> Point {
+    (a.0 - b.0, a.1 - b.1, a.2 - b.2)
+}
+
+fn dot(a: Point, b: Point) -> f64 {
+    a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+}
+
+fn cross(a: Point, b: Point) -> Point {
+    (
+        a.1 * b.2 - a.2 * b.1,
+        a.2 * b.0 - a.0 * b.2,
+        a.0 * b.1 - a.1 * b.0,
+    )
+}
+
+fn unit(a: Point) -> Option<Point> {
+    let n = dot(a, a).sqrt();
+    (n > 1e-9).then(|| (a.0 / n, a.1 / n, a.2 / n))
+}
+
+// The centre of the circle through coplanar points, or of the sphere through the others.
+// The plane normal only serves the fit, `polar_frame` fits the one a ring is solved about.
+fn fit_circle_or_sphere(points: &[Point]) -> Option<Point> {
+    let c = centroid(points);
+    // the plane normal, summed over consecutive pairs so that arcs work too
+    let normal = points
+        .iter()
+        .zip(points.iter().skip(1))
+        .fold((0.0, 0.0, 0.0), |n, (&p, &q)| {
+            let m = cross(sub(p, c), sub(q, c));
+            // keep a consistent orientation
+            if dot(m, n) < 0.0 {
+                sub(n, m)
+            } else {
+                (n.0 + m.0, n.1 + m.1, n.2 + m.2)
+            }
+        });
+    let normal = unit(normal)?;
+    let coplanar = points
+        .iter()
+        .all(|&p| Num::from(dot(sub(p, c), normal)).is_close(0));
+
+    if coplanar {
+        // fit in plane coordinates and lift back
+        let helper = if normal.0.abs() < 0.9 {
+            (1.0, 0.0, 0.0)
+        } else {
+            (0.0, 1.0, 0.0)
+        };
+        let u = unit(cross(normal, helper))?;
+        let v = cross(normal, u);
+        let plane: Vec<_> = points
+            .iter()
+            .map(|&p| (dot(sub(p, c), u), dot(sub(p, c), v)))
+            .collect();
+        let (a, b) = fit_circle(&plane)?;
+        Some((
+            c.0 + a * u.0 + b * v.0,
+            c.1 + a * u.1 + b * v.1,
+            c.2 + a * u.2 + b * v.2,
+        ))
+    } else {
+        let points: Vec<_> = points.iter().map(|p| vec![p.0, p.1, p.2]).collect();
+        let s = fit_center(&points)?;
+        Some((s[0], s[1], s[2]))
+    }
+}
+
+// The normal of the plane that fits the points best: the smallest singular vector of the
+// centred points, that is the eigenvector of their scatter matrix with the smallest
+// eigenvalue, found by Jacobi rotations. None for fewer than three points or a line.
+fn plane_normal(points: &[Point]) -> Option<Point> {
+    if points.len() < 3 {
+        return None;
+    }
+    let c = centroid(points);
+    let mut a = [[0.0; 3]; 3];
+    for &p in points {
+        let d = sub(p, c);
+        let d = [d.0, d.1, d.2];
+        for i in 0..3 {
+            for j in 0..3 {
+                a[i][j] += d[i] * d[j];
+            }
+        }
+    }
+    let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    for _ in 0..50 {
+        let (p, q) = [(0, 1), (0, 2), (1, 2)]
+            .iter()
+            .copied()
+            .max_by(|&(i, j), &(k, l)| a[i][j].abs().total_cmp(&a[k][l].abs()))
+            .unwrap();
+        if a[p][q].abs() < 1e-12 {
+            break;
+        }
+        // the rotation in the (p, q) plane that zeroes a[p][q]
+        let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+        let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+        let (cs, sn) = (1.0 / (t * t + 1.0).sqrt(), t / (t * t + 1.0).sqrt());
+        for row in a.iter_mut().chain(v.iter_mut()) {
+            let (x, y) = (row[p], row[q]);
+            row[p] = cs * x - sn * y;
+            row[q] = sn * x + cs * y;
+        }
+        let (x, y) = (a[p], a[q]);
+        a[p] = [0, 1, 2].map(|k| cs * x[k] - sn * y[k]);
+        a[q] = [0, 1, 2].map(|k| sn * x[k] + cs * y[k]);
+    }
+    let mut order = [0, 1, 2];
+    order.sort_by(|&i, &j| a[i][i].total_cmp(&a[j][j]));
+    // points on a line lie in many planes
+    if a[order[1]][order[1]] <= ABS_EPSILON {
+        return None;
+    }
+    let k = order[0];
+    unit((v[0][k], v[1][k], v[2][k]))
+}
+
+// A ring in a tilted plane, as the angles that rotate the xy plane onto its plane, and the
+// points rotated back into a horizontal plane. None for points that are not coplanar, and
+// for those that are horizontal already.
+fn polar_frame(list: &[Vec3]) -> Option<(Vec3, Vec<Vec3>)> {
+    let points: Vec<Point> = list
+        .iter()
+        .map(|v| (v.0.to_f64(), v.1.to_f64(), v.2.to_f64()))
+        .collect();
+    let n = plane_normal(&points)?;
+    let n = if n.2 < 0.0 { (-n.0, -n.1, -n.2) } else { n };
+    let c = centroid(&points);
+    let coplanar = points
+        .iter()
+        .all(|&p| Num::from(dot(sub(p, c), n)).is_close(0));
+    // rotating z about x by a and then about y by b gives (sin b cos a, -sin a, cos b cos a)
+    let fitted = ((-n.1).asin().to_degrees(), n.0.atan2(n.2).to_degrees(), 0.0);
+    if !coplanar || (close(fitted.0, 0.0) && close(fitted.1, 0.0)) {
+        return None;
+    }
+
+    let round = |v: f64| (v / SOLVE_ROUND).round() * SOLVE_ROUND + 0.0;
+    let rounded = (round(fitted.0), round(fitted.1), 0.0);
+    [rounded, fitted].iter().find_map(|&angles| {
+        let back = Affine3::rotate(angles).inverse()?;
+        let local: Vec<Point> = points.iter().map(|&p| back.apply(p)).collect();
+        let flat = local
+            .iter()
+            .all(|p| Num::from(p.2 - local[0].2).is_close(0));
+        let local = local
+            .into_iter()
+            .map(|p| (p.0.into(), p.1.into(), p.2.into()))
+            .collect();
+        let angles = (angles.0.into(), angles.1.into(), angles.2.into());
+        flat.then_some((angles, local))
+    })
+}
+
+// The largest minus the smallest distance of the points from a centre, without the point
+// that is left out.
+fn spread(points: &[Point], c: Point, out: Option<usize>) -> f64 {
+    let rs = points
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| Some(i) != out)
+        .map(|(_, &p)| dot(sub(p, c), sub(p, c)).sqrt());
+    let (lo, hi) = rs.fold((f64::INFINITY, 0.0f64), |(lo, hi), r| {
+        (lo.min(r), hi.max(r))
+    });
+    hi - lo
+}
+
+// Candidate polar centres, best first, each with the element it leaves out: the fitted
+// circle or sphere and the centroid of all elements, then fits leaving one element out
+// (for rings with an extra element, like a centre hole).
+//
+// Unpolar measures its angles about the z axis, so a ring in a tilted plane is rotated
+// into a horizontal one first, see `polar_frame`.
+fn polar_centers(list: &[Vec3]) -> Vec<(Point, Option<usize>)> {
+    let points: Vec<Point> = list
+        .iter()
+        .map(|v| (v.0.to_f64(), v.1.to_f64(), v.2.to_f64()))
+        .collect();
+    let mut fits: Vec<(Point, Option<usize>)> = fit_circle_or_sphere(&points)
+        .into_iter()
+        .chain(std::iter::once(centroid(&points)))
+        .map(|c| (c, None))
+        .collect();
+    if points.len() > 3 && points.len() <= POLAR_LEAVE_ONE_OUT_MAX {
+        let mut outs = vec![];
+        for i in 0..points.len() {
+            let mut rest = points.clone();
+            rest.remove(i);
+            outs.extend(fit_circle_or_sphere(&rest).map(|c| (c, Some(i))));
+        }
+        // prefer the centres that put the points closest to one radius
+        outs.sort_by(|&(a, i), &(b, j)| spread(&points, a, i).total_cmp(&spread(&points, b, j)));
+        fits.extend(outs);
+    }
+
+    let round = |v: f64| (v / SOLVE_ROUND).round() * SOLVE_ROUND;
+    let mut centers: Vec<(Point, Option<usize>)> = vec![];
+    for (c, out) in fits {
+        let rounded = (round(c.0), round(c.1), round(c.2));
+        for c in [rounded, c] {
+            let seen = centers
+                .iter()
+                .any(|&(d, o)| o == out && Num::from(dot(sub(c, d), sub(c, d))).is_close(0));
+            if !seen {
+                centers.push((c, out));
+            }
+        }
+    }
+    centers
+}
+
+// The list as the partitioner would split it: the solved elements, then element `i`.
+fn with_extra(egraph: &mut EGraph, rest: Id, list: &[Vec3], i: usize) -> Id {
+    let extra = add_vec(egraph, &list[i]);
+    let extra = egraph.add(Cad::List(vec![extra]));
+    let part = Partitioning::from_vec(vec![list.len() - 1, 1]);
+    let part = egraph.add(Cad::Partitioning(part));
+    let lists = egraph.add(Cad::List(vec![rest, extra]));
+    let unpart = egraph.add(Cad::Unpart([part, lists]));
+
+    let order: Vec<usize> = (0..list.len()).filter(|&j| j != i).chain([i]).collect();
+    let perm = Permutation::from_vec(&order);
+    if perm.is_ordered() {
+        unpart
+    } else {
+        let perm = egraph.add(Cad::Permutation(perm));
+        egraph.add(Cad::Unsort([perm, unpart]))
+    }
+}
+
+// Cylindrical coordinates (theta, r, h) about an axis through `center`.
+// Angles are unwrapped, so that a helix gets a linear theta.
+fn cylindrical(points: &[(f64, f64, f64)], center: (f64, f64)) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
+    let mut thetas: Vec<f64> = vec![];
+    let mut rs = vec![];
+    let mut hs = vec![];
+    for &(a, b, h) in points {
+        let (da, db) = (a - center.0, b - center.1);
+        let raw = db.atan2(da).to_degrees();
+        let theta = match thetas.last() {
+            Some(prev) => prev + normalize_angle(raw - prev),
+            None => raw,
+        };
+        thetas.push(theta);
+        rs.push((da * da + db * db).sqrt());
+        hs.push(h);
+    }
+    (thetas, rs, hs)
+}
+
+fn solve_nums(vs: &[f64]) -> Option<Formula> {
+    let nums: Vec<Num> = vs.iter().map(|&v| v.into()).collect();
+    solve_list_fn(&nums)
+}
+
+// Positions on a helix or spiral around an axis parallel to x, y or z, with theta linear in i
+// and r and h any formula in i, as
+// (MapI n (Vec3 (+ ca (* r (cos theta))) (+ cb (* r (sin theta))) h)) up to the axis order.
+fn solve_helix(egraph: &mut EGraph, list: &[Vec3]) -> Vec<Id> {
+    let mut results = vec![];
+    let points: Vec<_> = list
+        .iter()
+        .map(|v| (v.0.to_f64(), v.1.to_f64(), v.2.to_f64()))
+        .collect();
+
+    // coordinates as (a, b, h) with h along the axis, keeping the orientation
+    let axes: [fn(Point) -> Point; 3] = [
+        |p| (p.0, p.1, p.2),
+        |p| (p.1, p.2, p.0),
+        |p| (p.2, p.0, p.1),
+    ];
+    for (axis, to_axis) in axes.iter().enumerate() {
+        let local: Vec<_> = points.iter().map(|&p| to_axis(p)).collect();
+        let plane: Vec<_> = local.iter().map(|p| (p.0, p.1)).collect();
+        let fitted = match fit_circle(&plane) {
+            Some(c) => c,
+            None => continue,
+        };
+        let rounded = (
+            (fitted.0 / SOLVE_ROUND).round() * SOLVE_ROUND,
+            (fitted.1 / SOLVE_ROUND).round() * SOLVE_ROUND,
+        );
+
+        for &center in &[rounded, fitted] {
+            let (thetas, rs, hs) = cylindrical(&local, center);
+            if rs.iter().any(|&r| Num::from(r).is_close(0)) {
+                continue;
+            }
+            let (theta, r, h) = match (solve_nums(&thetas), solve_nums(&rs), solve_nums(&hs)) {
+                (Some(Formula::Deg1(theta)), Some(r), Some(h)) => (theta, r, h),
+                _ => continue,
+            };
+            let constant = |f: &Formula| match f {
+                Formula::Deg1(f) => f.a.is_close(0),
+                _ => false,
+            };
+            if theta.a.is_close(0) || (constant(&r) && constant(&h)) {
+                // not turning, or a plain ring, which the polar solver covers
+                continue;
+            }
+
+            // the formulas are checked one by one, check the positions they give
+            let theta = Formula::Deg1(theta);
+            let fits = local.iter().enumerate().try_fold(0.0f64, |max, (i, p)| {
+                let i = f(i);
+                let (t, r) = (theta.eval(i).to_radians(), r.eval(i));
+                let q = (center.0 + r * t.cos(), center.1 + r * t.sin(), h.eval(i));
+                [(q.0, p.0), (q.1, p.1), (q.2, p.2)]
+                    .iter()
+                    .try_fold(max, |max, &(a, b)| {
+                        close(a, b).then(|| max.max((a - b).abs()))
+                    })
+            });
+            let dev = match fits {
+                Some(dev) => dev,
+                None => continue,
+            };
 
-    for &known_n in NS {
-        if n == known_n.into() {
-            return egraph.add(Cad::Num(known_n.into()));
+            let e = &mut *egraph;
+            let i = eadd!(e, Cad::ListVar(LV("i")));
+            let t = theta.add_to_egraph(e, i);
+            let r = r.add_to_egraph(e, i);
+            let h = h.add_to_egraph(e, i);
+            let ca = add_num(e, center.0.into());
+            let cb = add_num(e, center.1.into());
+            let cos = eadd!(e, Cad::Cos, t);
+            let sin = eadd!(e, Cad::Sin, t);
+            let rcos = eadd!(e, Cad::Mul, r, cos);
+            let rsin = eadd!(e, Cad::Mul, r, sin);
+            let a = eadd!(e, Cad::Add, ca, rcos);
+            let b = eadd!(e, Cad::Add, cb, rsin);
+            let v = match axis {
+                0 => [a, b, h],
+                1 => [h, a, b],
+                _ => [b, h, a],
+            };
+            let v = eadd!(e, Cad::Vec3, v[0], v[1], v[2]);
+            let n = add_num(e, list.len().into());
+            results.push(e.add(Cad::MapI(vec![n, v])));
+            record_deviation(dev);
+            break;
+        }
+    }
+    results
+}
+
+// A long number is written in a closed form if it has one, like (/ 100 3).
+fn add_num(egraph: &mut EGraph, n: Num) -> Id {
+    if is_long(&n) {
+        if let Some(form) = closed_form(n.to_f64()) {
+            return add_form(egraph, form);
         }
     }
     egraph.add(Cad::Num(n))
 }
 
-fn add_vec(egraph: &mut EGraph, v: Vec3) -> Id {
-    let x = add_num(egraph, v.0);
-    let y = add_num(egraph, v.1);
-    let z = add_num(egraph, v.2);
+fn add_vec(egraph: &mut EGraph, v: &Vec3) -> Id {
+    let x = add_num(egraph, v.0.clone());
+    let y = add_num(egraph, v.1.clone());
+    let z = add_num(egraph, v.2.clone());
     egraph.add(Cad::Vec3([x, y, z]))
 }
 
 pub fn solve(egraph: &mut EGraph, list: &[Vec3]) -> Vec<Id> {
     let mut results = solve_vec(egraph, list);
     debug!("Solved {:?} -> {:?}", list, results);
-    let (center, polar_list) = polarize(list);
-    for res in solve_vec(egraph, &polar_list) {
-        let e = Cad::Unpolar([
-            add_num(egraph, list.len().into()),
-            add_vec(egraph, center),
-            res,
-        ]);
-        results.push(egraph.add(e));
+    // a ring in a tilted plane is solved in the frame of its plane and rotated back
+    let (angles, local) = match polar_frame(list) {
+        Some((angles, local)) => (Some(angles), local),
+        None => (None, list.to_vec()),
+    };
+    // the first centre that gives a solvable polar list wins
+    for (center, out) in polar_centers(&local) {
+        let kept: Vec<Vec3> = (0..local.len())
+            .filter(|&i| Some(i) != out)
+            .map(|i| local[i].clone())
+            .collect();
+        let polar_list = polarize(&kept, center);
+        let solved = solve_vec(egraph, &polar_list);
+        if solved.is_empty() {
+            continue;
+        }
+        let center = (center.0.into(), center.1.into(), center.2.into());
+        for res in solved {
+            let e = Cad::Unpolar([
+                add_num(egraph, kept.len().into()),
+                add_vec(egraph, &center),
+                res,
+            ]);
+            let mut unpolar = egraph.add(e);
+            if let Some(angles) = &angles {
+                let rotate = egraph.add(Cad::Rotate);
+                let angles = add_vec(egraph, angles);
+                unpolar = egraph.add(Cad::Affine([rotate, angles, unpolar]));
+            }
+            results.push(match out {
+                Some(i) => with_extra(egraph, unpolar, list, i),
+                None => unpolar,
+            });
+        }
+        break;
     }
+    results.extend(solve_helix(egraph, list));
     results
 }
 
+/// Formulas in `i` for a list of numbers, e.g. the radii of a row of spheres.
+/// Constant lists are left to `Repeat`.
+pub fn solve_nums_fn(egraph: &mut EGraph, list: &[Num]) -> Option<Id> {
+    if list.len() <= 2 || list.iter().all(|v| same(v, &list[0])) {
+        return None;
+    }
+    let fun = solve_list_fn(list)?;
+    debug!("Solved {:?} -> {:?}", list, fun);
+    record_deviation(max_deviation(list, &fun));
+    let i = egraph.add(Cad::ListVar(LV("i")));
+    Some(fun.add_to_egraph(egraph, i))
+}
+
+// Equal values, unlike `close` never looser than ABS_EPSILON
+fn same(a: &Num, b: &Num) -> bool {
+    (a.to_f64() - b.to_f64()).abs() <= ABS_EPSILON
+}
+
 fn chunk_length(list: &[Num]) -> usize {
-    if list.iter().all(|&x| x == list[0]) {
+    if list.iter().all(|x| same(x, &list[0])) {
         return list.len();
     }
 
@@ -312,7 +1379,7 @@ fn chunk_length(list: &[Num]) -> usize {
             && list
                 .chunks_exact(n)
                 .skip(1)
-                .all(|chunk| &list[..n] == chunk)
+                .all(|chunk| chunk.iter().zip(&list[..n]).all(|(a, b)| same(a, b)))
         {
             return n;
         }
@@ -326,9 +1393,9 @@ fn unrun(list: &[Num], n: usize) -> Option<Vec<Num>> {
         return None;
     }
 
-    let all_same = |slice: &[Num]| slice.iter().all(|&x| x == slice[0]);
+    let all_same = |slice: &[Num]| slice.iter().all(|x| same(x, &slice[0]));
     if list.chunks_exact(n).all(all_same) {
-        Some(list.iter().copied().step_by(n).collect())
+        Some(list.iter().cloned().step_by(n).collect())
     } else {
         None
     }
@@ -345,6 +1412,18 @@ mod tests {
         }}
     }
 
+    // runs f with another fit tolerance and restores the old one after, even on a panic
+    fn with_tolerance<T>(tolerance: f64, f: impl FnOnce() -> T) -> T {
+        struct Restore(f64);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                TOLERANCE.with(|tolerance| tolerance.set(self.0));
+            }
+        }
+        let _restore = Restore(TOLERANCE.with(|t| t.replace(tolerance)));
+        f()
+    }
+
     #[test]
     fn test_chunk_length() {
         let nums = nums![0, 0, 0];
@@ -369,14 +1448,14 @@ mod tests {
     fn deg1_test1() {
         let input = nums![1, 2, 3, 4];
         let res = solve_deg1(&input).unwrap();
-        assert_eq!(res.a, 1.0);
+        assert_eq!(res.a, num(1));
     }
 
     #[test]
     fn deg1_test2() {
         let input = nums![0, 0, 0, 0];
         let res = solve_deg1(&input).unwrap();
-        assert_eq!(res.a, 0.0);
+        assert_eq!(res.a, num(0));
     }
 
     #[test]
@@ -385,11 +1464,89 @@ mod tests {
         assert_eq!(solve_deg1(&input), None);
     }
 
+    #[test]
+    fn deg1_noisy() {
+        // well above the epsilon of Num::is_close, so only fit with a looser tolerance
+        let input: Vec<Num> = [0.003, 1.997, 4.004, 5.996]
+            .iter()
+            .map(|&v| v.into())
+            .collect();
+        assert_eq!(solve_deg1(&input), None);
+
+        let res = with_tolerance(0.01, || solve_deg1(&input)).unwrap();
+        assert_eq!((res.a, res.b), (num(2), num(0)));
+    }
+
+    #[test]
+    fn deviation_of_added_formulas() {
+        use crate::cad::MetaAnalysis;
+
+        let mut egraph = EGraph::new(MetaAnalysis);
+        let xs: Vec<Num> = [0.003, 1.997, 4.004, 5.996]
+            .iter()
+            .map(|&v| v.into())
+            .collect();
+        let zeros = nums![0, 0, 0, 0];
+        take_max_deviation();
+        with_tolerance(0.01, || {
+            // x fits, but y does not, so nothing is added
+            let ys = nums![0, 5, 1, 7];
+            assert_eq!(solve_and_add(&mut egraph, &xs, &ys, &zeros), None);
+            assert_eq!(take_max_deviation(), 0.0);
+
+            assert!(solve_and_add(&mut egraph, &xs, &zeros, &zeros).is_some());
+            assert!(Num::from(take_max_deviation()).is_close(0.004));
+        });
+    }
+
+    #[test]
+    fn brick_bond() {
+        use crate::cad::MetaAnalysis;
+
+        // 3 courses of 4 bricks, every other one shifted by half a brick
+        let (mut xs, mut ys) = (vec![], vec![]);
+        for row in 0..3 {
+            for col in 0..4 {
+                xs.push(num(20 * col + 10 * (row % 2)));
+                ys.push(num(10 * row));
+            }
+        }
+        let zs = vec![num(0); 12];
+
+        let mut egraph = EGraph::new(MetaAnalysis);
+        let id = solve_lattice(&mut egraph, &xs, &ys, &zs).unwrap();
+        let bond = "(MapI 3 4 (Vec3 (+ (* 20 j) (* 10 (% i 2))) (* 10 i) 0))";
+        assert_eq!(egraph.lookup_expr(&bond.parse().unwrap()), Some(id));
+
+        // one brick out of line
+        xs[11] = num(70);
+        assert_eq!(solve_lattice(&mut egraph, &xs, &ys, &zs), None);
+    }
+
     #[test]
     fn deg2_test1() {
         let input = nums![0, 1, 4, 9];
         let res = solve_deg2(&input).unwrap();
-        assert_eq!(res.a, 1.0);
+        assert_eq!(res.a, 1.into());
+    }
+
+    #[test]
+    #[cfg(feature = "exact")]
+    fn deg1_exact() {
+        let third = |n: i32| Num::from(n).checked_div(&num(3)).unwrap();
+        let input: Vec<Num> = (0..4).map(|i| third(100 * i + 1)).collect();
+        let res = solve_deg1(&input).unwrap();
+        assert_eq!((res.a, res.b), (third(100), third(1)));
+    }
+
+    #[test]
+    #[cfg(feature = "exact")]
+    fn deg2_exact() {
+        let third = |n: i32| Num::from(n).checked_div(&num(3)).unwrap();
+        let input: Vec<Num> = (0..5).map(|i| third(i * i)).collect();
+        let res = solve_deg2(&input).unwrap();
+        assert_eq!(res.a, third(1));
+        assert_eq!((res.b, res.c), (0.into(), 0.into()));
     }
 
     #[test]
@@ -397,4 +1554,150 @@ mod tests {
         let input = nums![0, 1, 14, 9];
         assert_eq!(solve_deg2(&input), None);
     }
+
+    #[test]
+    fn cubic() {
+        let input = nums![1, 2, 9, 28, 65, 126];
+        let res = solve_list_fn(&input).unwrap();
+        assert_eq!(res, Formula::Poly(nums![1, 0, 0, 1]));
+    }
+
+    #[test]
+    fn geometric() {
+        let input = nums![3, 5, 9, 17, 33];
+        let res = solve_list_fn(&input).unwrap();
+        assert_eq!(
+            res,
+            Formula::Geom {
+                a: num(2),
+                r: num(2),
+                b: num(1)
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "exact")]
+    fn geometric_exact() {
+        // 1 - (1/3)^i, where a float fit gives r as 0.3333333333333333
+        let third = num(1).checked_div(&num(3)).unwrap();
+        let mut input = vec![];
+        let mut power = num(1);
+        for _ in 0..5 {
+            input.push(&num(1) - &power);
+            power = &power * &third;
+        }
+        let res = solve_list_fn(&input).unwrap();
+        assert_eq!(
+            res,
+            Formula::Geom {
+                a: num(-1),
+                r: third,
+                b: num(1)
+            }
+        );
+    }
+
+    #[test]
+    fn periodic() {
+        let input = nums![0, 5, 10, 0, 5, 10, 0];
+        let res = solve_list_fn(&input).unwrap();
+        assert_eq!(res.eval(7.0), 5.0);
+        assert!(matches!(res, Formula::Periodic(3, _)));
+    }
+
+    #[test]
+    fn count_down_range() {
+        let fun = || solve_list_fn(&nums![20, 16, 12, 8, 4]).unwrap();
+        let mut funs = vec![(0, fun()), (1, fun())];
+        assert_eq!(choose_range(&mut funs), (num(20), num(-4)));
+        let (a, b) = (num(1), num(0));
+        assert_eq!(funs[0].1, Formula::Deg1(Deg1 { a, b }));
+
+        // a range that only removes one offset is not worth its bounds
+        let mut funs = vec![(0, fun())];
+        assert_eq!(choose_range(&mut funs), (num(0), num(1)));
+    }
+
+    #[test]
+    fn fit_circle_arc() {
+        // a quarter arc, whose centroid is far from the centre
+        let points: Vec<_> = (0..4)
+            .map(|i| {
+                let t = (30.0 * f(i)).to_radians();
+                (3.0 + 2.0 * t.cos(), -1.0 + 2.0 * t.sin())
+            })
+            .collect();
+        let (x, y) = fit_circle(&points).unwrap();
+        assert!(Num::from(x).is_close(3.0) && Num::from(y).is_close(-1.0));
+    }
+
+    #[test]
+    fn cylindrical_unwraps() {
+        let points: Vec<_> = (0..6)
+            .map(|i| {
+                let t = (100.0 * f(i)).to_radians();
+                (t.cos(), t.sin(), f(i))
+            })
+            .collect();
+        let (thetas, _, _) = cylindrical(&points, (0.0, 0.0));
+        let res = solve_nums(&thetas).unwrap();
+        let (a, b) = (num(100), num(0));
+        assert_eq!(res, Formula::Deg1(Deg1 { a, b }));
+    }
+
+    #[test]
+    fn polar_center_of_tilted_arc() {
+        // an arc in the plane x = 1, so the circle fit has to find the plane first
+        let list: Vec<Vec3> = (0..4)
+            .map(|i| {
+                let t = (40.0 * f(i)).to_radians();
+                (
+                    1.into(),
+                    (2.0 + 5.0 * t.cos()).into(),
+                    (5.0 * t.sin()).into(),
+                )
+            })
+            .collect();
+        let (c, out) = polar_centers(&list)[0];
+        assert_eq!(out, None);
+        assert!(Num::from(c.0).is_close(1.0));
+        assert!(Num::from(c.1).is_close(2.0));
+        assert!(Num::from(c.2).is_close(0.0));
+    }
+
+    #[test]
+    fn unpolar_of_tilted_arc() {
+        use crate::base::geom::rotate_point;
+        use crate::cad::MetaAnalysis;
+
+        // an arc about (10, 0, 0) in a plane tilted by 30 degrees about x
+        let list: Vec<Vec3> = (0..5)
+            .map(|i| {
+                let t = (30.0 * f(i)).to_radians();
+                let p = (10.0 + 5.0 * t.cos(), 5.0 * t.sin(), 0.0);
+                let p = rotate_point((30.0, 0.0, 0.0), p);
+                (p.0.into(), p.1.into(), p.2.into())
+            })
+            .collect();
+        let mut egraph = EGraph::new(MetaAnalysis);
+        let results = solve(&mut egraph, &list);
+
+        // rotated back from an Unpolar with linear angles, not a polynomial about z
+        let angles = egraph.lookup_expr(&"(Vec3 30 0 0)".parse().unwrap());
+        let has = |id: Id, f: &dyn Fn(&Cad) -> bool| egraph[id].nodes.iter().any(f);
+        let unpolar = |id: Id| {
+            has(id, &|n| match n {
+                Cad::Unpolar([_, _, params]) => has(*params, &|n| matches!(n, Cad::MapI(_))),
+                _ => false,
+            })
+        };
+        let rotated = results.iter().any(|&id| {
+            has(id, &|n| match n {
+                Cad::Affine([_, a, inner]) => Some(*a) == angles && unpolar(*inner),
+                _ => false,
+            })
+        });
+        assert!(rotated);
+    }
 }
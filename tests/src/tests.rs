@@ -1,4 +1,4 @@
-#[warn(non_snake_case)] // TODO: remove it
+#![warn(non_snake_case)] // TODO: remove it
 use std::ffi::OsStr;
 use std::io::Write;
 
@@ -28,9 +28,15 @@ use serde::Serialize;
 
 use egg::*;
 use rewrite::cad::{Cad, MetaAnalysis};
+use rewrite::check::{trace_sample, trace_unions, UnionTrace};
 use rewrite::cost::{Cost, CostFn};
+use rewrite::export::raster::{Image, Raster, View};
 use rewrite::export::scad::Scad;
+use rewrite::hyperparameters::RASTER_WIDTH;
+use rewrite::measure::{area, volume};
 use rewrite::prune::remove_empty;
+use rewrite::snap::number_hook;
+use rewrite::solve::take_max_deviation;
 use std::default::Default;
 
 #[derive(Serialize)]
@@ -49,6 +55,19 @@ pub struct RunResult {
     pub ast_depth: usize,
     pub n_mapis: usize,
     pub depth_under_mapis: usize,
+
+    // geometry, should be unchanged by optimisation
+    pub initial_volume: Option<f64>,
+    pub final_volume: Option<f64>,
+    pub initial_area: Option<f64>,
+    pub final_area: Option<f64>,
+
+    // the largest deviation of the data from a fitted formula
+    pub max_fit_deviation: f64,
+
+    // re-validated unions, with SZ_TRACE_SAMPLE set
+    pub pre_union_trace: Option<UnionTrace>,
+    pub union_trace: Option<UnionTrace>,
 }
 
 fn ast_size_impl(expr: &RecExpr<Cad>, id: Id) -> usize {
@@ -122,10 +141,30 @@ impl IterationData<Cad, MetaAnalysis> for MyIterData {
 
 type MyRunner = egg::Runner<Cad, MetaAnalysis, MyIterData>;
 
-pub fn optimize(input: &str) -> (String, RunResult) {
-    const ITERATIONS: usize = 50000;
-    const NODE_LIMIT: usize = 3000000;
-    const TIMEOUT: usize = 1;
+/// Top-down renders of the program before and after optimisation, in the same frame.
+pub struct Preview {
+    pub initial: Option<Image>,
+    pub final_: Option<Image>,
+}
+
+impl Preview {
+    fn new(initial: &RecExpr<Cad>, best: &RecExpr<Cad>) -> Self {
+        let raster = Raster::fit(initial, RASTER_WIDTH);
+        let render = |expr| raster.and_then(|r| r.render(expr, View::Projection));
+        Preview {
+            initial: render(initial),
+            final_: render(best),
+        }
+    }
+}
+
+pub fn optimize(input: &str) -> (String, RunResult, Preview) {
+    // None of the e-graphs saturates, so every run stops on a limit. Iterations and nodes
+    // bound the float run to about two minutes; the slower exact mode can still reach the
+    // time limit.
+    const ITERATIONS: usize = 30;
+    const NODE_LIMIT: usize = 20000;
+    const TIMEOUT: usize = 60;
     const PRE_EXTRACT: bool = true;
 
     println!("input is {}", input);
@@ -138,31 +177,48 @@ pub fn optimize(input: &str) -> (String, RunResult) {
     let initial_expr = out;
 
     let initial_cost = CostFn.cost_rec(&initial_expr);
+    let initial_volume = volume(&initial_expr);
+    let initial_area = area(&initial_expr);
+    let source_expr = initial_expr.clone();
+
+    // count only the fits of this run
+    take_max_deviation();
+    let sample = trace_sample();
+    let new_runner = || {
+        let runner = MyRunner::new(MetaAnalysis).with_hook(number_hook);
+        if sample > 0 {
+            runner.with_explanations_enabled()
+        } else {
+            runner
+        }
+    };
+    let trace = |runner: &MyRunner| (sample > 0).then(|| trace_unions(&runner.egraph, sample));
 
-    let initial_expr = if PRE_EXTRACT {
+    let (initial_expr, pre_union_trace) = if PRE_EXTRACT {
         let pre_rules = rewrite::rules::pre_rules();
-        let runner = MyRunner::new(MetaAnalysis::default())
+        let runner = new_runner()
             .with_iter_limit(ITERATIONS)
             .with_node_limit(NODE_LIMIT)
-            .with_time_limit(Duration::from_secs_f64(1.0))
+            .with_time_limit(Duration::from_secs_f64(TIMEOUT as f64))
             .with_expr(&initial_expr)
             .run(&pre_rules);
-        Extractor::new(&runner.egraph, CostFn)
+        let best = Extractor::new(&runner.egraph, CostFn)
             .find_best(runner.roots[0])
-            .1
+            .1;
+        (best, trace(&runner))
     } else {
-        initial_expr
+        (initial_expr, None)
     };
 
     let rules = rewrite::rules::rules();
-    let runner = MyRunner::new(MetaAnalysis::default())
+    let runner = new_runner()
         .with_iter_limit(ITERATIONS)
         .with_node_limit(NODE_LIMIT)
         .with_time_limit(Duration::from_secs_f64(TIMEOUT as f64))
         .with_scheduler(
             BackoffScheduler::default()
                 .with_ban_length(5)
-                .with_initial_match_limit(1_000_00),
+                .with_initial_match_limit(100_000),
         )
         .with_expr(&initial_expr)
         .run(&rules);
@@ -174,6 +230,7 @@ pub fn optimize(input: &str) -> (String, RunResult) {
     );
 
     runner.print_report();
+    let union_trace = trace(&runner);
 
     let root = runner.roots[0];
     let extract_time = Instant::now();
@@ -195,15 +252,30 @@ pub fn optimize(input: &str) -> (String, RunResult) {
         ast_depth: ast_depth(&best.1),
         n_mapis: n_mapis(&best.1),
         depth_under_mapis: depth_under_mapis(&best.1),
+        initial_volume,
+        final_volume: volume(&best.1),
+        initial_area,
+        final_area: area(&best.1),
+        max_fit_deviation: take_max_deviation(),
+        pre_union_trace,
+        union_trace,
     };
 
-    (best.1.pretty(80), report)
+    let preview = Preview::new(&source_expr, &best.1);
+
+    (best.1.pretty(80), report, preview)
 }
 // ============================================
 
 const PROGRAM_DIR: &str = "program";
+// the exact mode prints fractions like 100/3, so it has its own references
+#[cfg(not(feature = "exact"))]
 const REF_DIR: &str = "ref";
+#[cfg(feature = "exact")]
+const REF_DIR: &str = "ref-exact";
 const REPORT_DIR: &str = "report";
+// relative, loose enough for the Monte Carlo estimates
+const VOLUME_TOLERANCE: f64 = 0.02;
 
 #[derive(Debug, Clone, Parser)]
 #[clap(name = "bin-test", author)]
@@ -270,14 +342,14 @@ fn run(test_case: TestCase, args: &Args, world: &TestWorld) -> bool {
     stdout.write_all(name.as_bytes()).unwrap();
 
     let src_program = std::fs::read_to_string(program_path).expect("Unable to read file");
-    let (res_program, report) = optimize(&src_program);
+    let (res_program, report, preview) = optimize(&src_program);
     if let Ok(ref_program) = std::fs::read_to_string(ref_program_path) {
         if !compare(&res_program, &ref_program) {
             if args.update {
                 export_program(ref_program_path, &res_program);
                 updated = true;
             } else {
-                writeln!(stdout, "  Does not match reference image.").unwrap();
+                writeln!(stdout, "  Does not match reference program.").unwrap();
                 ok = false;
             }
         }
@@ -286,7 +358,30 @@ fn run(test_case: TestCase, args: &Args, world: &TestWorld) -> bool {
             export_program(ref_program_path, &res_program);
             updated = true;
         } else {
-            writeln!(stdout, "  Failed to open reference image.").unwrap();
+            writeln!(stdout, "  Failed to open reference program.").unwrap();
+            ok = false;
+        }
+    }
+
+    // a run cut short by the budget may hide a search that got worse
+    if !matches!(
+        report.stop_reason,
+        StopReason::Saturated | StopReason::IterationLimit(_)
+    ) {
+        writeln!(stdout, "  Stopped on {:?}.", report.stop_reason).unwrap();
+    }
+
+    if let (Some(before), Some(after)) = (report.initial_volume, report.final_volume) {
+        if (before - after).abs() > VOLUME_TOLERANCE * before.abs().max(after.abs()) {
+            writeln!(stdout, "  Volume changed from {} to {}.", before, after).unwrap();
+            ok = false;
+        }
+    }
+
+    let traces = [&report.pre_union_trace, &report.union_trace];
+    for trace in traces.iter().copied().flatten() {
+        for mismatch in &trace.mismatches {
+            writeln!(stdout, "  Unsound union:\n{}", mismatch).unwrap();
             ok = false;
         }
     }
@@ -296,6 +391,15 @@ fn run(test_case: TestCase, args: &Args, world: &TestWorld) -> bool {
     let out_file = std::fs::File::create(report_path).expect("failed to open output");
     serde_json::to_writer_pretty(out_file, &report).unwrap();
 
+    // previews next to the report, for review only; the refs are the programs
+    let images = [(preview.initial, "initial.png"), (preview.final_, "png")];
+    for (image, extension) in images {
+        if let Some(image) = image {
+            std::fs::write(report_path.with_extension(extension), image.to_png())
+                .expect("failed to write image");
+        }
+    }
+
     if updated {
         writeln!(stdout, "program update  ✔").unwrap();
     }
@@ -351,7 +455,7 @@ fn main() {
         .collect::<Vec<_>>();
 
     let len = results.len();
-    let ok = results.iter().map(|_| 1).sum::<usize>();
+    let ok = results.iter().filter(|&&ok| ok).count();
     if len >= 1 {
         println!("{ok} / {len} tests passed.");
     } else {
@@ -364,7 +468,7 @@ fn main() {
     if ok != len {
         println!(
             "Set the UPDATE_EXPECT environment variable or pass the \
-             --update flag to update the reference image(s)."
+             --update flag to update the reference program(s)."
         );
     }
 